@@ -1,65 +1,1197 @@
+use crate::settings::{LightConfig, LightId, MyAppSettings, OffPolicy, MIN_CHECK_INTERVAL};
+use crate::utils::camera::{self, ActivityDetector};
+use crate::utils::discovery::{self, DiscoveredLight};
+use crate::utils::light::{AccessoryInfo, KeyLightClient, LightError, LightSettings, LightState};
+use eframe::egui;
+use reqwest::Client;
+use std::future::Future;
+use std::io;
 use std::net::IpAddr;
-use std::str::FromStr;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::Sender;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, watch};
 
+/// Minimum time between two attempts to re-resolve the same light.
+const RELOCATE_BACKOFF: Duration = Duration::from_secs(30);
+/// Upper bound for a single request to a light, so that an unreachable
+/// light doesn't hold up the others.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Minimum time between two brightness or temperature changes sent to the
+/// same light. Slider updates in between are coalesced into the latest value.
+const ADJUST_INTERVAL: Duration = Duration::from_millis(100);
+/// How long to wait before trying again to switch a light that failed,
+/// unless the lights' target changes in the meantime.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+/// How long to listen for mDNS answers when discovering lights.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+/// Per-host timeout and parallelism of the subnet scan.
+const SCAN_TIMEOUT: Duration = Duration::from_millis(800);
+const SCAN_CONCURRENCY: usize = 32;
+
+/// Commands sent from the UI to the worker, applied while it keeps running.
+/// Lights are referred to by their [`LightId`].
 pub enum BackgroundCommand {
     Stop,
+    /// Switch between following the camera and manual control.
+    SetMode(Mode),
+    /// Apply changed settings.
+    UpdateConfig(MyAppSettings),
+    /// Turn a light on or off by hand.
+    SetPower { light: LightId, on: bool },
+    /// Sent on every slider move; see [`ADJUST_INTERVAL`].
+    SetBrightness { light: LightId, brightness: u8 },
+    /// Color temperature in Kelvin.
+    SetTemperature { light: LightId, temperature: u16 },
+    /// Read every light's state and device info and report them.
+    RefreshState,
+    /// Make a light flash so it can be told apart from others.
+    Identify { light: LightId },
+    /// Change the name the device reports.
+    Rename { light: LightId, name: String },
+    /// Read a light's persistent device settings and report them.
+    LoadDeviceSettings { light: LightId },
+    ApplyDeviceSettings { light: LightId, settings: LightSettings },
+    /// Look for lights via mDNS.
+    DiscoverLights,
+    /// Probe every host of an IPv4 CIDR range for lights.
+    ScanSubnet(String),
+}
+
+/// How the worker drives the lights.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Follow the camera.
+    #[default]
+    Auto,
+    ForcedOn,
+    ForcedOff,
+    /// Leave the lights alone until the next camera session starts, then
+    /// go back to [`Mode::Auto`].
+    Paused,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 4] = [Mode::Auto, Mode::ForcedOn, Mode::ForcedOff, Mode::Paused];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Mode::Auto => "Auto",
+            Mode::ForcedOn => "Forced on",
+            Mode::ForcedOff => "Forced off",
+            Mode::Paused => "Paused until next call",
+        }
+    }
+}
+
+/// Something that happened in the worker, with the time it happened.
+#[derive(Debug, Clone)]
+pub struct WorkerEvent {
+    pub time: SystemTime,
+    pub kind: EventKind,
+}
+
+impl From<EventKind> for WorkerEvent {
+    fn from(kind: EventKind) -> Self {
+        WorkerEvent {
+            time: SystemTime::now(),
+            kind,
+        }
+    }
+}
+
+/// Events sent from the worker to the UI. Lights are referred to by their
+/// [`LightId`], so that events still on their way when a light is removed or
+/// pointed at another device are dropped instead of applied to another one.
+#[derive(Debug, Clone)]
+pub enum EventKind {
+    /// The debounced camera state changed; `apps` are the applications
+    /// allowed to trigger the lights.
+    CameraStateChanged { active: bool, apps: Vec<String> },
+    /// The applications using the watched device changed, whether or not
+    /// the app rules let them trigger the lights.
+    ActiveApps(Vec<String>),
+    /// The worker changed its mode by itself, e.g. a pause ended.
+    ModeChanged(Mode),
+    /// A light was switched, or its state was read.
+    LightStateChanged { light: LightId, state: LightState },
+    /// A request to a light failed.
+    DeviceUnreachable { light: LightId, error: String },
+    /// The device details of a light were read.
+    AccessoryInfo { light: LightId, info: AccessoryInfo },
+    /// The persistent device settings of a light were read or applied.
+    DeviceSettings { light: LightId, settings: LightSettings },
+    /// Lights found by a discovery command, or why the search failed.
+    Discovered(Result<Vec<DiscoveredLight>, String>),
+    /// The light with `serial_number` answered on a new address.
+    Relocated {
+        serial_number: String,
+        ip_address: String,
+    },
+    /// Checking the camera failed.
+    DetectionFailed(String),
+    /// A [`BackgroundCommand::UpdateConfig`] took effect.
+    ConfigApplied,
+    Stopped,
+}
+
+impl EventKind {
+    /// The light the event is about, if any.
+    pub fn light(&self) -> Option<LightId> {
+        match self {
+            EventKind::LightStateChanged { light, .. }
+            | EventKind::DeviceUnreachable { light, .. }
+            | EventKind::AccessoryInfo { light, .. }
+            | EventKind::DeviceSettings { light, .. } => Some(*light),
+            _ => None,
+        }
+    }
+}
+
+/// Sends events to the UI and wakes it up, so they are shown right away
+/// rather than on the next repaint.
+#[derive(Clone)]
+pub struct EventSender {
+    tx: Sender<WorkerEvent>,
+    ctx: egui::Context,
+}
+
+impl EventSender {
+    pub fn new(tx: Sender<WorkerEvent>, ctx: egui::Context) -> Self {
+        Self { tx, ctx }
+    }
+
+    pub fn send(&self, event: EventKind) {
+        let _ = self.tx.send(event.into());
+        self.ctx.request_repaint();
+    }
+}
+
+/// Tracks which lights have been switched to follow the camera.
+///
+/// Holds no I/O, so the on/off decisions can be checked by feeding it
+/// readings, e.g. from a `ScriptedDetector` in tests.
+///
+/// Readings are debounced: the camera counts as active once it has been in
+/// use for `on_delay`, and as released once it has been idle for `off_delay`.
+/// Shorter blips, like a device probe or a settings preview, are ignored.
+///
+/// The first reading is taken as is and reconciled with the lights' state at
+/// startup: lights that are already on during a call, or that were left as
+/// they are while the camera is idle, are not switched.
+///
+/// In the forced modes the lights ignore the camera; a pause lasts until the
+/// debounced camera state next turns active.
+pub struct Automation {
+    mode: Mode,
+    on_delay: Duration,
+    off_delay: Duration,
+    /// Latest raw reading and when it last flipped.
+    reading: bool,
+    reading_since: Instant,
+    /// Debounced camera state the lights follow.
+    camera_active: bool,
+    /// Whether each light has been switched to match `camera_active`.
+    synced: Vec<bool>,
+    /// Whether each light was on at startup, until the first reading.
+    initially_on: Option<Vec<bool>>,
+    /// Counts the times the debounced camera state turned active.
+    session: u64,
+}
+
+impl Automation {
+    pub fn new(initially_on: Vec<bool>, on_delay: Duration, off_delay: Duration) -> Self {
+        Self {
+            mode: Mode::Auto,
+            on_delay,
+            off_delay,
+            reading: false,
+            reading_since: Instant::now(),
+            camera_active: false,
+            synced: vec![false; initially_on.len()],
+            initially_on: Some(initially_on),
+            session: 0,
+        }
+    }
+
+    /// Records a camera reading taken at `now` and returns the lights that
+    /// still have to be switched to match [`target`](Self::target).
+    pub fn update(&mut self, reading: bool, now: Instant) -> Vec<usize> {
+        if let Some(initially_on) = self.initially_on.take() {
+            self.reading = reading;
+            self.reading_since = now;
+            self.camera_active = reading;
+            self.session += reading as u64;
+            self.synced = initially_on.iter().map(|&on| self.reconciled(on)).collect();
+        } else if reading != self.reading {
+            self.reading = reading;
+            self.reading_since = now;
+        }
+        if self.pending_for(now) == Some(Duration::ZERO) {
+            self.camera_active = reading;
+            self.session += reading as u64;
+            match self.mode {
+                Mode::Auto => self.synced.fill(false),
+                Mode::Paused if reading => {
+                    self.mode = Mode::Auto;
+                    self.synced.fill(false);
+                }
+                Mode::Paused | Mode::ForcedOn | Mode::ForcedOff => {}
+            }
+        }
+        (0..self.synced.len()).filter(|&i| !self.synced[i]).collect()
+    }
+
+    /// Replaces the tracked lights. Lights that were tracked already keep
+    /// their state; new ones are reconciled with the current state like at
+    /// startup.
+    pub fn reset_lights(&mut self, lights: Vec<TrackedLight>) {
+        if let Some(initially_on) = &self.initially_on {
+            let lights_on = lights
+                .iter()
+                .map(|light| match *light {
+                    TrackedLight::Kept(i) => initially_on[i],
+                    TrackedLight::New { on } => on,
+                })
+                .collect();
+            self.synced = vec![false; lights.len()];
+            self.initially_on = Some(lights_on);
+        } else {
+            self.synced = lights
+                .iter()
+                .map(|light| match *light {
+                    TrackedLight::Kept(i) => self.synced[i],
+                    TrackedLight::New { on } => self.reconciled(on),
+                })
+                .collect();
+        }
+    }
+
+    pub fn set_delays(&mut self, on_delay: Duration, off_delay: Duration) {
+        self.on_delay = on_delay;
+        self.off_delay = off_delay;
+    }
+
+    /// Whether a light that is `on` needs no switching: during a call if it
+    /// is on already, outside of one never, since it may have been set by hand.
+    fn reconciled(&self, on: bool) -> bool {
+        match self.mode {
+            Mode::Auto => on || !self.camera_active,
+            Mode::ForcedOn | Mode::ForcedOff => on == self.target(),
+            Mode::Paused => true,
+        }
+    }
+
+    /// Debounced camera state.
+    pub fn is_camera_active(&self) -> bool {
+        self.camera_active
+    }
+
+    /// Identifies the current or last camera session, so that state saved
+    /// during one session isn't mistaken for state from another, e.g. when a
+    /// pause skipped the end of a call.
+    pub fn session(&self) -> u64 {
+        self.session
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Changes the mode; every light is switched to match the new mode,
+    /// except when pausing, which leaves them as they are.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.synced.fill(mode == Mode::Paused);
+    }
+
+    /// Whether the lights should be on.
+    pub fn target(&self) -> bool {
+        match self.mode {
+            Mode::Auto | Mode::Paused => self.camera_active,
+            Mode::ForcedOn => true,
+            Mode::ForcedOff => false,
+        }
+    }
+
+    /// Time left at `now` until the latest reading takes effect, or `None`
+    /// if it already has.
+    pub fn pending_for(&self, now: Instant) -> Option<Duration> {
+        if self.reading == self.camera_active {
+            return None;
+        }
+        let delay = if self.reading { self.on_delay } else { self.off_delay };
+        Some(delay.saturating_sub(now.saturating_duration_since(self.reading_since)))
+    }
+
+    /// Marks `light` as switched to the current target.
+    pub fn switched(&mut self, light: usize) {
+        self.synced[light] = true;
+    }
+}
+
+/// A light in a new light list, see [`Automation::reset_lights`].
+#[derive(Debug, Clone, Copy)]
+pub enum TrackedLight {
+    /// The light that was tracked at this position before.
+    Kept(usize),
+    /// A light that wasn't tracked before, and whether it is on.
+    New { on: bool },
 }
 
-pub fn run(ip: String, port: u16, cmd_rx: Receiver<BackgroundCommand>, log_tx: Sender<String>, check_interval_ms: u32, brightness: u8, temperature: u16) {
-    let camera_check_interval = Duration::from_millis(check_interval_ms as u64);
-    let mut is_light_on = false;
+/// Runs the worker until it receives [`BackgroundCommand::Stop`] or the UI
+/// goes away.
+///
+/// Requests to the lights run on one tokio runtime and share one HTTP
+/// client, so connections are reused. The detector blocks while it waits for
+/// changes and gets a thread of its own, see [`spawn_detector`].
+pub fn run(
+    settings: MyAppSettings,
+    detector: Box<dyn ActivityDetector + Send>,
+    cmd_rx: mpsc::UnboundedReceiver<BackgroundCommand>,
+    events: EventSender,
+) {
+    let (runtime, http) = match setup() {
+        Ok(setup) => setup,
+        Err(e) => {
+            eprintln!("Failed to start the background worker: {}", e);
+            events.send(EventKind::Stopped);
+            return;
+        }
+    };
+    runtime.block_on(async {
+        let worker = Worker::new(settings, detector, http, events).await;
+        worker.run(cmd_rx).await;
+    });
+}
+
+/// Builds the runtime and the HTTP client the worker runs on.
+fn setup() -> Result<(tokio::runtime::Runtime, Client), Box<dyn std::error::Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let http = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+    Ok((runtime, http))
+}
+
+/// Reads `detector` on a thread of its own and sends every reading to the
+/// worker. The thread ends once the worker drops the receiver.
+fn spawn_detector(
+    mut detector: Box<dyn ActivityDetector + Send>,
+    interval: watch::Receiver<Duration>,
+) -> mpsc::UnboundedReceiver<io::Result<Vec<String>>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    thread::spawn(move || {
+        let mut changed = true;
+        loop {
+            let mut failed = false;
+            if changed {
+                let reading = detector.active_apps();
+                failed = reading.is_err();
+                if tx.send(reading).is_err() {
+                    return;
+                }
+            } else if tx.is_closed() {
+                return;
+            }
+            // Returns early when the device is opened or closed. A failed
+            // reading is retried even if nothing changed.
+            let timeout = *interval.borrow();
+            changed = detector.wait_for_change(timeout) || failed;
+        }
+    });
+    rx
+}
+
+/// The detector's interval; settings from the registry aren't checked by the
+/// UI, so the minimum is enforced here too.
+fn check_interval(settings: &MyAppSettings) -> Duration {
+    Duration::from_millis(settings.check_interval.max(MIN_CHECK_INTERVAL) as u64)
+}
+
+/// State of the background worker.
+struct Worker {
+    /// Settings other than the lights, which live in `lights`.
+    settings: MyAppSettings,
+    lights: Vec<WorkerLight>,
+    /// Shared by every light, so that connections are reused.
+    http: Client,
+    /// Readings from the detector thread.
+    readings: mpsc::UnboundedReceiver<io::Result<Vec<String>>>,
+    /// How long the detector waits for a change before it checks again.
+    interval: watch::Sender<Duration>,
+    automation: Automation,
+    /// Applications last reported by the detector; `None` until its first
+    /// reading.
+    active_apps: Option<Vec<String>>,
+    /// Debounced camera state last reported to the UI.
+    camera_active: Option<bool>,
+    /// When to try again to switch the lights that failed; `None` if every
+    /// light was switched.
+    next_retry: Option<Instant>,
+    events: EventSender,
+}
+
+impl Worker {
+    async fn new(
+        mut settings: MyAppSettings,
+        detector: Box<dyn ActivityDetector + Send>,
+        http: Client,
+        events: EventSender,
+    ) -> Self {
+        let lights = std::mem::take(&mut settings.lights);
+        let automation = Automation::new(
+            Vec::new(),
+            Duration::from_millis(settings.on_delay as u64),
+            Duration::from_millis(settings.off_delay as u64),
+        );
+        let (interval, interval_rx) = watch::channel(check_interval(&settings));
+        let mut worker = Self {
+            settings,
+            lights: Vec::new(),
+            http,
+            readings: spawn_detector(detector, interval_rx),
+            interval,
+            automation,
+            active_apps: None,
+            camera_active: None,
+            next_retry: None,
+            events,
+        };
+        worker.load_lights(lights).await;
+        worker
+    }
+
+    fn send(&self, event: EventKind) {
+        self.events.send(event);
+    }
+
+    /// Handles commands and camera readings as they come in, until stopped.
+    async fn run(mut self, mut commands: mpsc::UnboundedReceiver<BackgroundCommand>) {
+        loop {
+            self.send_adjustments().await;
+            self.step().await;
+            // Wake up in time for a debounced transition, a coalesced
+            // adjustment or a retry even if nothing else happens.
+            let now = Instant::now();
+            let adjust = self.lights.iter().filter_map(|l| l.adjust_pending_for(now)).min();
+            let retry = self.next_retry.map(|retry| retry.saturating_duration_since(now));
+            let wake = [self.automation.pending_for(now), retry, adjust]
+                .into_iter()
+                .flatten()
+                .min();
+            tokio::select! {
+                cmd = commands.recv() => match cmd {
+                    Some(BackgroundCommand::Stop) | None => break,
+                    Some(cmd) => self.handle(cmd).await,
+                },
+                Some(reading) = self.readings.recv() => self.record(reading),
+                _ = tokio::time::sleep(wake.unwrap_or_default()), if wake.is_some() => {}
+            }
+        }
+        self.send(EventKind::Stopped);
+    }
+
+    /// Starts tracking `lights`. Lights already tracked for the same device
+    /// are kept as they are, with their restore snapshot and pending
+    /// adjustments. The others are read and picked up as they are instead of
+    /// being reset, so that a new light list doesn't end a running call.
+    async fn load_lights(&mut self, lights: Vec<LightConfig>) {
+        let mut old: Vec<Option<WorkerLight>> = std::mem::take(&mut self.lights).into_iter().map(Some).collect();
+        let mut tracked = Vec::with_capacity(lights.len());
+        for config in lights {
+            let kept = old
+                .iter_mut()
+                .enumerate()
+                .find(|(_, light)| light.as_ref().is_some_and(|light| light.is_same_device(&config)))
+                .and_then(|(i, light)| Some((i, light.take()?)));
+            if let Some((i, mut light)) = kept {
+                light.update_config(config);
+                self.lights.push(light);
+                tracked.push(TrackedLight::Kept(i));
+            } else {
+                self.lights.push(WorkerLight::new(config, self.http.clone()));
+                tracked.push(TrackedLight::New { on: false });
+            }
+        }
+        for (i, light) in tracked.iter_mut().enumerate() {
+            if let TrackedLight::New { on } = light {
+                // Don't ask a light for its state if it didn't answer already.
+                *on = self.read_info(i).await && self.read_state(i).await;
+            }
+        }
+        self.automation.reset_lights(tracked);
+        self.next_retry = None;
+    }
 
-    if IpAddr::from_str(&ip).is_err() {
-        let _ = log_tx.send("Invalid IP address".into());
-        return;
+    /// Reads and reports the device info and state of every light; returns
+    /// whether each one is on. Lights that can't be read count as off.
+    async fn read_all(&mut self) -> Vec<bool> {
+        let mut lights_on = Vec::with_capacity(self.lights.len());
+        for i in 0..self.lights.len() {
+            // Don't ask a light for its state if it didn't answer already.
+            let on = self.read_info(i).await && self.read_state(i).await;
+            lights_on.push(on);
+        }
+        lights_on
     }
 
-    if let Err(e) = crate::utils::light::set_state(false, &ip, port, brightness, temperature) {
-        let _ = log_tx.send(format!("Failed to change light state: {}", e));
-        return;
+    /// Reads and reports the device info of `light`, learning its serial
+    /// number if it isn't known yet. Returns `true` on success.
+    async fn read_info(&mut self, light: usize) -> bool {
+        let res = self.lights[light]
+            .with_relocation(&self.events, |client| async move { client.accessory_info().await })
+            .await;
+        let Some(info) = self.report(light, res) else {
+            return false;
+        };
+        let config = &mut self.lights[light].config;
+        if config.serial_number.is_empty() {
+            config.serial_number = info.serial_number.clone();
+        }
+        let light = config.id;
+        self.send(EventKind::AccessoryInfo { light, info });
+        true
     }
-    let _ = log_tx.send("Light turned off initially".into());
 
-    loop {
-        // Check for Stop command
-        if let Ok(BackgroundCommand::Stop) = cmd_rx.try_recv() {
-            let _ = log_tx.send("Stopped camera control".into());
-            break;
+    /// Reads and reports the state of `light`; returns whether it is on.
+    async fn read_state(&mut self, light: usize) -> bool {
+        let res = self.lights[light].read_state(&self.events).await;
+        let Some(state) = self.report(light, res) else {
+            return false;
+        };
+        let config = &mut self.lights[light].config;
+        config.light_on = state.is_on();
+        config.brightness = state.brightness;
+        config.temperature = state.kelvin();
+        let light = config.id;
+        self.send(EventKind::LightStateChanged { light, state });
+        state.is_on()
+    }
+
+    /// Position of the light with `id`, if it is still configured.
+    fn find(&self, id: LightId) -> Option<usize> {
+        self.lights.iter().position(|l| l.config.id == id)
+    }
+
+    /// Reports a failed request to `light`; returns the result on success.
+    fn report<T>(&self, light: usize, res: Result<T, LightError>) -> Option<T> {
+        match res {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.send(EventKind::DeviceUnreachable {
+                    light: self.lights[light].config.id,
+                    error: e.to_string(),
+                });
+                None
+            }
         }
-        match crate::utils::camera::is_enabled() {
-            Ok(is_camera_enabled) => {
-                if is_camera_enabled && !is_light_on {
-                    if let Err(e) = crate::utils::light::set_state(true, &ip, port, brightness, temperature) {
-                        let _ = log_tx.send(format!("Failed to turn on the light: {}", e));
-                    } else {
-                        let _ = log_tx.send("Camera access is enabled".into());
-                        is_light_on = true;
+    }
+
+    async fn handle(&mut self, cmd: BackgroundCommand) {
+        match cmd {
+            BackgroundCommand::Stop => {}
+            BackgroundCommand::SetMode(mode) => {
+                self.automation.set_mode(mode);
+                self.next_retry = None;
+            }
+            BackgroundCommand::UpdateConfig(settings) => self.apply_config(settings).await,
+            BackgroundCommand::SetPower { light, on } => {
+                if let Some(i) = self.find(light) {
+                    let config = &self.lights[i].config;
+                    let state = LightState::new(on, config.brightness, config.temperature);
+                    self.set_state(i, state).await;
+                }
+            }
+            BackgroundCommand::SetBrightness { light, brightness } => {
+                if let Some(i) = self.find(light) {
+                    self.lights[i].config.brightness = brightness;
+                    self.lights[i].adjusted = true;
+                }
+            }
+            BackgroundCommand::SetTemperature { light, temperature } => {
+                if let Some(i) = self.find(light) {
+                    self.lights[i].config.temperature = temperature;
+                    self.lights[i].adjusted = true;
+                }
+            }
+            BackgroundCommand::RefreshState => {
+                self.read_all().await;
+            }
+            BackgroundCommand::Identify { light } => {
+                if let Some(i) = self.find(light) {
+                    let (client, index) = (self.lights[i].client(), self.lights[i].config.index);
+                    let events = self.events.clone();
+                    // Blinking takes a while, keep handling commands meanwhile.
+                    tokio::spawn(async move {
+                        if let Err(e) = client.identify(index).await {
+                            let error = e.to_string();
+                            events.send(EventKind::DeviceUnreachable { light, error });
+                        }
+                    });
+                }
+            }
+            BackgroundCommand::Rename { light, name } => {
+                if let Some(i) = self.find(light) {
+                    let res = self.lights[i]
+                        .with_relocation(&self.events, |client| {
+                            let name = name.clone();
+                            async move { client.set_display_name(&name).await }
+                        })
+                        .await;
+                    if self.report(i, res).is_some() {
+                        self.read_info(i).await;
                     }
-                } else if !is_camera_enabled && is_light_on {
-                    if let Err(e) = crate::utils::light::set_state(false, &ip, port, brightness, temperature) {
-                        let _ = log_tx.send(format!("Failed to turn off the light: {}", e));
-                    } else {
-                        let _ = log_tx.send("Camera access is disabled".into());
-                        is_light_on = false;
+                }
+            }
+            BackgroundCommand::LoadDeviceSettings { light } => {
+                if let Some(i) = self.find(light) {
+                    let res = self.lights[i]
+                        .with_relocation(&self.events, |client| async move { client.settings().await })
+                        .await;
+                    if let Some(settings) = self.report(i, res) {
+                        self.send(EventKind::DeviceSettings { light, settings });
                     }
-                } else {
-                    let _ = log_tx.send(format!(
-                        "Camera access is {}",
-                        if is_camera_enabled {
-                            "enabled"
-                        } else {
-                            "disabled"
-                        }
-                    ));
                 }
             }
-            Err(e) => {
-                let _ = log_tx.send(format!("Failed to check camera access: {}", e));
+            BackgroundCommand::ApplyDeviceSettings { light, settings } => {
+                if let Some(i) = self.find(light) {
+                    let res = self.lights[i]
+                        .with_relocation(&self.events, |client| async move { client.set_settings(&settings).await })
+                        .await;
+                    if self.report(i, res).is_some() {
+                        self.send(EventKind::DeviceSettings { light, settings });
+                    }
+                }
+            }
+            BackgroundCommand::DiscoverLights => {
+                let events = self.events.clone();
+                // The mDNS daemon blocks while it listens for answers.
+                tokio::task::spawn_blocking(move || {
+                    let found = discovery::browse_mdns(discovery::ELGATO_SERVICE, DISCOVERY_TIMEOUT)
+                        .map_err(|e| e.to_string());
+                    events.send(EventKind::Discovered(found));
+                });
+            }
+            BackgroundCommand::ScanSubnet(range) => {
+                let events = self.events.clone();
+                tokio::spawn(async move {
+                    let found = discovery::scan_subnet(&range, discovery::ELGATO_PORT, SCAN_CONCURRENCY, SCAN_TIMEOUT)
+                        .await
+                        .map_err(|e| e.to_string());
+                    events.send(EventKind::Discovered(found));
+                });
+            }
+        }
+    }
+
+    async fn apply_config(&mut self, mut settings: MyAppSettings) {
+        let lights = std::mem::take(&mut settings.lights);
+        if settings.trigger != self.settings.trigger {
+            // Dropping the old receiver ends the old detector thread.
+            self.readings = spawn_detector(camera::detector_for(settings.trigger), self.interval.subscribe());
+        }
+        self.interval.send_replace(check_interval(&settings));
+        self.automation.set_delays(
+            Duration::from_millis(settings.on_delay as u64),
+            Duration::from_millis(settings.off_delay as u64),
+        );
+        self.settings = settings;
+        self.load_lights(lights).await;
+        self.send(EventKind::ConfigApplied);
+    }
+
+    /// Sets and reports the state of `light`. Returns `true` on success.
+    async fn set_state(&mut self, light: usize, state: LightState) -> bool {
+        let res = self.lights[light].switch(state, &self.events).await;
+        if self.report(light, res).is_none() {
+            return false;
+        }
+        self.lights[light].config.light_on = state.is_on();
+        let light = self.lights[light].config.id;
+        self.send(EventKind::LightStateChanged { light, state });
+        true
+    }
+
+    /// Sends the latest brightness and temperature to the lights that were
+    /// adjusted, at most once per [`ADJUST_INTERVAL`] each. The UI already
+    /// shows these values, so only failures are reported.
+    async fn send_adjustments(&mut self) {
+        let now = Instant::now();
+        for i in 0..self.lights.len() {
+            let light = &mut self.lights[i];
+            if light.adjust_pending_for(now) != Some(Duration::ZERO) {
+                continue;
+            }
+            light.adjusted = false;
+            light.last_adjusted = Some(now);
+            let state = LightState::new(light.config.light_on, light.config.brightness, light.config.temperature);
+            let res = light.switch(state, &self.events).await;
+            self.report(i, res);
+        }
+    }
+
+    /// Takes a reading from the detector thread.
+    fn record(&mut self, reading: io::Result<Vec<String>>) {
+        match reading {
+            Ok(apps) if self.active_apps.as_ref() != Some(&apps) => {
+                self.send(EventKind::ActiveApps(apps.clone()));
+                self.active_apps = Some(apps);
+            }
+            Ok(_) => {}
+            Err(e) => self.send(EventKind::DetectionFailed(e.to_string())),
+        }
+    }
+
+    /// Switches the lights that don't match the camera state yet. Lights
+    /// that couldn't be switched are tried again after [`RETRY_INTERVAL`],
+    /// or as soon as the target changes.
+    async fn step(&mut self) {
+        // The first reading is reconciled with the lights, so don't guess.
+        let Some(active_apps) = &self.active_apps else {
+            return;
+        };
+        // The UI shows every app so that rules can be added for them,
+        // but only the allowed ones switch the lights on.
+        let rules = &self.settings.app_rules;
+        let allowed: Vec<String> = active_apps.iter().filter(|app| rules.allows(app)).cloned().collect();
+        let mode = self.automation.mode();
+        let now = Instant::now();
+        let pending = self.automation.update(!allowed.is_empty(), now);
+        if self.automation.mode() != mode {
+            self.next_retry = None;
+            self.send(EventKind::ModeChanged(self.automation.mode()));
+        }
+        let active = self.automation.is_camera_active();
+        if self.camera_active != Some(active) {
+            self.next_retry = None;
+            self.camera_active = Some(active);
+            self.send(EventKind::CameraStateChanged { active, apps: allowed });
+        }
+        if pending.is_empty() {
+            self.next_retry = None;
+            return;
+        }
+        if self.next_retry.is_some_and(|retry| now < retry) {
+            return;
+        }
+
+        let target = self.automation.target();
+        let session = self.automation.session();
+        let mut unswitched = false;
+        for i in pending {
+            let light = &mut self.lights[i];
+            let off = LightState::new(false, light.config.brightness, light.config.temperature);
+            let state = if target {
+                // Remember what the light looked like before the call.
+                let taken = light.snapshot.is_some_and(|(taken_in, _)| taken_in == session);
+                if !taken && self.settings.off_policy == OffPolicy::Restore {
+                    light.snapshot = light.read_state(&self.events).await.ok().map(|state| (session, state));
+                }
+                LightState::new(true, light.config.brightness, light.config.temperature)
+            } else if self.automation.mode() == Mode::ForcedOff {
+                // Keep the snapshot for when automation resumes.
+                off
+            } else {
+                light
+                    .snapshot
+                    .take()
+                    .filter(|&(taken_in, _)| taken_in == session)
+                    .map_or(off, |(_, state)| state)
+            };
+            if self.set_state(i, state).await {
+                self.automation.switched(i);
+            } else {
+                unswitched = true;
+                if !target && state.is_on() {
+                    // Try restoring again next time.
+                    self.lights[i].snapshot = Some((session, state));
+                }
+            }
+        }
+        self.next_retry = unswitched.then(|| Instant::now() + RETRY_INTERVAL);
+    }
+}
+
+/// A light as seen by the worker.
+struct WorkerLight {
+    config: LightConfig,
+    http: Client,
+    /// When the light was last looked for by serial number.
+    last_relocate: Option<Instant>,
+    /// State to restore once the camera is released, with the camera
+    /// session it was taken in.
+    snapshot: Option<(u64, LightState)>,
+    /// Whether brightness or temperature changed since they were last sent.
+    adjusted: bool,
+    /// When brightness and temperature were last sent.
+    last_adjusted: Option<Instant>,
+}
+
+impl WorkerLight {
+    fn new(config: LightConfig, http: Client) -> Self {
+        Self {
+            config,
+            http,
+            last_relocate: None,
+            snapshot: None,
+            adjusted: false,
+            last_adjusted: None,
+        }
+    }
+
+    /// Whether `config` refers to this light. A missing serial number
+    /// matches, since the UI may not have learned it yet.
+    fn is_same_device(&self, config: &LightConfig) -> bool {
+        self.config.id == config.id
+            && self.config.ip_address == config.ip_address
+            && self.config.port == config.port
+            && self.config.index == config.index
+            && (config.serial_number.is_empty() || self.config.serial_number == config.serial_number)
+    }
+
+    /// Takes over changed settings of the same device, such as its name or
+    /// on-values, keeping what the worker learned about it.
+    fn update_config(&mut self, config: LightConfig) {
+        let serial_number = if config.serial_number.is_empty() {
+            std::mem::take(&mut self.config.serial_number)
+        } else {
+            config.serial_number.clone()
+        };
+        self.config = LightConfig {
+            light_on: self.config.light_on,
+            serial_number,
+            ..config
+        };
+    }
+
+    /// Time left at `now` until a pending brightness or temperature change
+    /// may be sent, or `None` if there is none.
+    fn adjust_pending_for(&self, now: Instant) -> Option<Duration> {
+        if !self.adjusted {
+            return None;
+        }
+        Some(self.last_adjusted.map_or(Duration::ZERO, |sent| {
+            ADJUST_INTERVAL.saturating_sub(now.saturating_duration_since(sent))
+        }))
+    }
+
+    /// Client for the light's current address.
+    fn client(&self) -> KeyLightClient {
+        KeyLightClient::with_client(self.http.clone(), &self.config.ip_address, self.config.port)
+    }
+
+    async fn switch(&mut self, state: LightState, events: &EventSender) -> Result<(), LightError> {
+        let index = self.config.index;
+        self.with_relocation(events, |client| async move { client.set_light(index, state).await })
+            .await
+    }
+
+    async fn read_state(&mut self, events: &EventSender) -> Result<LightState, LightError> {
+        let index = self.config.index;
+        self.with_relocation(events, |client| async move { client.light(index).await })
+            .await
+    }
+
+    /// Sends `request` to the light. If it can't be resolved or reached, its
+    /// address is re-resolved by serial number and the request is retried once.
+    async fn with_relocation<T, F, Fut>(&mut self, events: &EventSender, request: F) -> Result<T, LightError>
+    where
+        F: Fn(KeyLightClient) -> Fut,
+        Fut: Future<Output = Result<T, LightError>>,
+    {
+        let res = request(self.client()).await;
+        if matches!(res, Err(LightError::Resolve(..) | LightError::Http(_))) && self.relocate(events).await {
+            return request(self.client()).await;
+        }
+        res
+    }
+
+    /// Looks for the light on the network by its serial number and updates
+    /// its address. Returns `true` if the light was found on a new address.
+    ///
+    /// Lights configured by hostname are left alone: the client re-resolves
+    /// the hostname by itself, and replacing it with an address would lose it.
+    async fn relocate(&mut self, events: &EventSender) -> bool {
+        let light = &mut self.config;
+        let Ok(current) = light.ip_address.parse::<IpAddr>() else {
+            return false;
+        };
+        if light.serial_number.is_empty() || self.last_relocate.is_some_and(|t| t.elapsed() < RELOCATE_BACKOFF) {
+            return false;
+        }
+        self.last_relocate = Some(Instant::now());
+
+        let Some(ip) = discovery::locate(&self.http, &light.serial_number, &light.hostname, light.port).await else {
+            return false;
+        };
+        if ip.parse::<IpAddr>().is_ok_and(|ip| ip == current) {
+            return false;
+        }
+        events.send(EventKind::Relocated {
+            serial_number: light.serial_number.clone(),
+            ip_address: ip.clone(),
+        });
+        light.ip_address = ip;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::camera::ScriptedDetector;
+
+    const ON_DELAY: Duration = Duration::from_secs(1);
+    const OFF_DELAY: Duration = Duration::from_secs(10);
+
+    fn at(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn follows_scripted_detector() {
+        let mut detector = ScriptedDetector::new([false, true, true, false, false]);
+        let mut automation = Automation::new(vec![false], ON_DELAY, OFF_DELAY);
+        let start = Instant::now();
+        let mut targets = Vec::new();
+        for millis in [0, 1_000, 2_500, 3_000, 14_000] {
+            let reading = !detector.active_apps().unwrap().is_empty();
+            for light in automation.update(reading, at(start, millis)) {
+                automation.switched(light);
             }
+            targets.push(automation.target());
+        }
+        assert_eq!(targets, [false, false, true, true, false]);
+    }
+
+    /// Starts with the camera idle and the light off at `start`.
+    fn idle(start: Instant) -> Automation {
+        let mut automation = Automation::new(vec![false], ON_DELAY, OFF_DELAY);
+        assert!(automation.update(false, start).is_empty());
+        automation
+    }
+
+    /// Feeds a reading and marks the returned lights as switched.
+    fn read(automation: &mut Automation, reading: bool, now: Instant) -> bool {
+        for light in automation.update(reading, now) {
+            automation.switched(light);
+        }
+        automation.target()
+    }
+
+    #[test]
+    fn ignores_blip_shorter_than_on_delay() {
+        let start = Instant::now();
+        let mut automation = idle(start);
+        assert!(!read(&mut automation, true, at(start, 100)));
+        assert!(!read(&mut automation, false, at(start, 600)));
+        assert!(!read(&mut automation, false, at(start, 5_000)));
+        assert!(!automation.is_camera_active());
+    }
+
+    #[test]
+    fn turns_on_after_on_delay() {
+        let start = Instant::now();
+        let mut automation = idle(start);
+        assert!(!read(&mut automation, true, at(start, 100)));
+        assert!(!read(&mut automation, true, at(start, 1_099)));
+        assert_eq!(automation.update(true, at(start, 1_100)), [0]);
+        assert!(automation.target());
+    }
+
+    #[test]
+    fn turns_off_only_after_off_delay() {
+        let start = Instant::now();
+        let mut automation = idle(start);
+        read(&mut automation, true, start);
+        assert!(read(&mut automation, true, at(start, 1_000)));
+        assert!(read(&mut automation, false, at(start, 2_000)));
+        assert!(read(&mut automation, false, at(start, 11_999)));
+        assert_eq!(automation.update(false, at(start, 12_000)), [0]);
+        assert!(!automation.target());
+    }
+
+    #[test]
+    fn reading_flipping_back_cancels_turn_off() {
+        let start = Instant::now();
+        let mut automation = idle(start);
+        read(&mut automation, true, start);
+        assert!(read(&mut automation, true, at(start, 1_000)));
+        assert!(read(&mut automation, false, at(start, 2_000)));
+        assert!(read(&mut automation, true, at(start, 5_000)));
+        assert_eq!(automation.pending_for(at(start, 5_000)), None);
+        // The earlier release doesn't count once the camera is idle again.
+        assert!(read(&mut automation, false, at(start, 6_000)));
+        assert!(read(&mut automation, false, at(start, 12_000)));
+        assert!(automation.update(false, at(start, 15_999)).is_empty());
+        assert!(!read(&mut automation, false, at(start, 16_000)));
+    }
+
+    #[test]
+    fn pending_for_reports_time_until_transition() {
+        let start = Instant::now();
+        let mut automation = idle(start);
+        assert_eq!(automation.pending_for(start), None);
+
+        read(&mut automation, true, at(start, 200));
+        assert_eq!(automation.pending_for(at(start, 200)), Some(ON_DELAY));
+        assert_eq!(automation.pending_for(at(start, 700)), Some(Duration::from_millis(500)));
+        assert_eq!(automation.pending_for(at(start, 5_000)), Some(Duration::ZERO));
+
+        read(&mut automation, true, at(start, 1_200));
+        assert_eq!(automation.pending_for(at(start, 1_200)), None);
+
+        read(&mut automation, false, at(start, 3_000));
+        assert_eq!(automation.pending_for(at(start, 4_000)), Some(Duration::from_secs(9)));
+    }
+
+    #[test]
+    fn startup_during_call_leaves_lights_already_on() {
+        let mut automation = Automation::new(vec![true], ON_DELAY, OFF_DELAY);
+        assert!(automation.update(true, Instant::now()).is_empty());
+        assert!(automation.is_camera_active());
+    }
+
+    #[test]
+    fn startup_during_call_switches_lights_that_are_off() {
+        let mut automation = Automation::new(vec![true, false], ON_DELAY, OFF_DELAY);
+        assert_eq!(automation.update(true, Instant::now()), [1]);
+        assert!(automation.target());
+    }
+
+    #[test]
+    fn startup_while_idle_leaves_lights_alone() {
+        let mut automation = Automation::new(vec![true, false], ON_DELAY, OFF_DELAY);
+        assert!(automation.update(false, Instant::now()).is_empty());
+        assert!(!automation.is_camera_active());
+    }
+
+    #[test]
+    fn reset_lights_before_first_reading_waits_for_it() {
+        let mut automation = Automation::new(vec![true], ON_DELAY, OFF_DELAY);
+        automation.reset_lights(vec![TrackedLight::Kept(0), TrackedLight::New { on: false }]);
+        assert_eq!(automation.update(true, Instant::now()), [1]);
+    }
+
+    #[test]
+    fn reset_lights_reconciles_with_current_camera_state() {
+        let start = Instant::now();
+        let mut automation = idle(start);
+        automation.reset_lights(vec![TrackedLight::New { on: true }, TrackedLight::New { on: false }]);
+        assert!(automation.update(false, at(start, 100)).is_empty());
+
+        read(&mut automation, true, at(start, 200));
+        assert!(read(&mut automation, true, at(start, 1_200)));
+        automation.reset_lights(vec![
+            TrackedLight::New { on: true },
+            TrackedLight::New { on: false },
+            TrackedLight::Kept(1),
+        ]);
+        assert_eq!(automation.update(true, at(start, 1_300)), [1]);
+    }
+
+    #[test]
+    fn call_after_pause_is_new_session() {
+        let start = Instant::now();
+        let mut automation = idle(start);
+        read(&mut automation, true, start);
+        assert!(read(&mut automation, true, at(start, 1_000)));
+        let first = automation.session();
+
+        // The call ends while paused, so nothing is restored.
+        automation.set_mode(Mode::Paused);
+        assert!(automation.update(false, at(start, 2_000)).is_empty());
+        assert!(automation.update(false, at(start, 12_000)).is_empty());
+        assert_eq!(automation.session(), first);
+
+        // The next call ends the pause; state saved during the first call
+        // must not be restored after it.
+        read(&mut automation, true, at(start, 20_000));
+        assert_eq!(automation.update(true, at(start, 21_000)), [0]);
+        assert_eq!(automation.mode(), Mode::Auto);
+        assert_ne!(automation.session(), first);
+    }
+
+    #[test]
+    fn forced_on_ignores_readings() {
+        let start = Instant::now();
+        let mut automation = idle(start);
+        automation.set_mode(Mode::ForcedOn);
+        assert_eq!(automation.update(false, at(start, 100)), [0]);
+        automation.switched(0);
+        assert!(automation.target());
+
+        for (millis, reading) in [(200, true), (2_000, true), (3_000, false), (20_000, false)] {
+            assert!(automation.update(reading, at(start, millis)).is_empty());
+            assert!(automation.target());
         }
-        thread::sleep(camera_check_interval);
+        assert_eq!(automation.mode(), Mode::ForcedOn);
+    }
+
+    #[test]
+    fn forced_off_ignores_readings() {
+        let start = Instant::now();
+        let mut automation = idle(start);
+        read(&mut automation, true, start);
+        assert!(read(&mut automation, true, at(start, 1_000)));
+        automation.set_mode(Mode::ForcedOff);
+        assert_eq!(automation.update(true, at(start, 1_100)), [0]);
+        automation.switched(0);
+        assert!(!automation.target());
+
+        for (millis, reading) in [(2_000, false), (15_000, false), (16_000, true), (20_000, true)] {
+            assert!(automation.update(reading, at(start, millis)).is_empty());
+            assert!(!automation.target());
+        }
+        assert_eq!(automation.mode(), Mode::ForcedOff);
+    }
+
+    #[test]
+    fn pause_leaves_lights_alone() {
+        let start = Instant::now();
+        let mut automation = idle(start);
+        read(&mut automation, true, start);
+        assert!(read(&mut automation, true, at(start, 1_000)));
+        automation.set_mode(Mode::Paused);
+        assert!(automation.update(true, at(start, 1_100)).is_empty());
+        assert!(automation.update(false, at(start, 2_000)).is_empty());
+        assert!(automation.update(false, at(start, 12_000)).is_empty());
+        assert_eq!(automation.mode(), Mode::Paused);
+    }
+
+    #[test]
+    fn pause_ends_with_next_debounced_activation() {
+        let start = Instant::now();
+        let mut automation = idle(start);
+        automation.set_mode(Mode::Paused);
+
+        // A blip doesn't count as the next call.
+        assert!(automation.update(true, at(start, 100)).is_empty());
+        assert!(automation.update(false, at(start, 500)).is_empty());
+        assert_eq!(automation.mode(), Mode::Paused);
+
+        assert!(automation.update(true, at(start, 1_000)).is_empty());
+        assert_eq!(automation.update(true, at(start, 2_000)), [0]);
+        assert_eq!(automation.mode(), Mode::Auto);
+        assert!(automation.target());
+    }
+
+    #[test]
+    fn reset_lights_keeps_state_of_tracked_lights() {
+        let start = Instant::now();
+        let mut automation = Automation::new(vec![false, false], ON_DELAY, OFF_DELAY);
+        assert!(automation.update(false, start).is_empty());
+        read(&mut automation, true, at(start, 100));
+        // The first light couldn't be switched on.
+        assert_eq!(automation.update(true, at(start, 1_100)), [0, 1]);
+        automation.switched(1);
+
+        // Adding a light keeps the failed one pending and doesn't switch
+        // the second one again.
+        automation.reset_lights(vec![
+            TrackedLight::Kept(1),
+            TrackedLight::Kept(0),
+            TrackedLight::New { on: true },
+        ]);
+        assert_eq!(automation.update(true, at(start, 1_200)), [1]);
     }
 }
@@ -3,10 +3,15 @@
 
 use consts::APPNAME;
 use eframe::egui;
+#[cfg(windows)]
 use std::sync::Mutex;
+#[cfg(windows)]
 use tray_icon::{MouseButtonState, TrayIconBuilder, TrayIconEvent};
+#[cfg(windows)]
 use windows::Win32::Foundation::HWND;
+#[cfg(windows)]
 use windows::Win32::UI::WindowsAndMessaging::{ShowWindow, SW_HIDE, SW_SHOWDEFAULT, SetForegroundWindow};
+#[cfg(windows)]
 use winit::raw_window_handle::{HasWindowHandle, RawWindowHandle, Win32WindowHandle};
 
 mod background;
@@ -20,12 +25,15 @@ use ui::MyApp;
 use std::fs::File;
 use std::io::Write;
 
-
+#[cfg(windows)]
 static VISIBLE: Mutex<bool> = Mutex::new(false);
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     set_panic_hook();
     let icon_image: IconImage = load_icon_from_memory(TRAY_ICON_LIT_BYTES)?;
+    // The window starts hidden in the tray on Windows; other platforms have no
+    // tray icon, so the window is shown right away.
+    #[cfg(windows)]
     let _tray_icon = TrayIconBuilder::new()
         .with_icon(icon_image.to_tray_icon())
         .with_tooltip(APPNAME)
@@ -34,7 +42,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Launch eframe
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
-            .with_visible(false)
+            .with_visible(!cfg!(windows))
             .with_icon(icon_image.to_egui_icon()),
         centered: true,
         ..Default::default()
@@ -45,22 +53,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         options,
         Box::new(|cc| {
             // Hook tray click event
+            #[cfg(windows)]
             match cc.window_handle().map(|h| h.as_raw()) {
                 Ok(RawWindowHandle::Win32(handle)) => setup_tray_icon_click_handler(handle),
                 Ok(_) => eprintln!("Tray icon click handler only supports Win32 window handle"),
                 Err(e) => eprintln!("Failed to get window handle: {}", e),
             }
 
-            Ok(Box::new(MyApp::default()))
+            Ok(Box::new(MyApp::new(&cc.egui_ctx)))
         }),
     )?;
     Ok(())
 }
 
+#[cfg(windows)]
 fn handle_to_hwnd(handle: Win32WindowHandle) -> HWND {
     HWND(handle.hwnd.get() as *mut std::ffi::c_void)
 }
 
+#[cfg(windows)]
 fn setup_tray_icon_click_handler(handle: Win32WindowHandle) {
     TrayIconEvent::set_event_handler(Some(move |event| {
         if let TrayIconEvent::Click {
@@ -1,97 +1,147 @@
-// Helper functions to load and save settings in the registry.
-use crate::consts::*;
-use crate::utils::light;
-use winreg::enums::{HKEY_CURRENT_USER, KEY_READ};
-use winreg::RegKey;
+// Application settings and helper functions to load and save them: in the
+// registry on Windows, in a JSON file elsewhere.
+use crate::utils::camera::{AppRules, Trigger};
+use crate::utils::light::{AccessoryInfo, LightSettings};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
 
-/// Application settings.
-#[derive(Debug)]
-pub struct MyAppSettings {
+#[cfg(not(windows))]
+mod file;
+#[cfg(windows)]
+mod registry;
+
+#[cfg(not(windows))]
+pub use file::*;
+#[cfg(windows)]
+pub use registry::*;
+
+/// Identifies a configured light while the app runs, independent of its
+/// position in the list.
+pub type LightId = u64;
+
+/// Returns an id that no other light has.
+pub fn new_light_id() -> LightId {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A single Key Light endpoint controlled by the application.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightConfig {
+    /// Changes when the light is pointed at another device; not persisted.
+    #[serde(skip)]
+    pub id: LightId,
+    pub name: String,
+    /// Serial number reported by the device; empty until it has been reached.
+    pub serial_number: String,
+    /// mDNS hostname the light was discovered under, used to re-resolve it.
+    pub hostname: String,
     pub ip_address: String,
     pub port: u16,
-    pub check_interval: u32,
+    /// Position of the light in the device's `lights` array.
+    pub index: usize,
     pub brightness: u8,
     pub temperature: u16,
+    #[serde(skip)]
     pub light_on: bool,
+    /// Device details read from the light; not persisted.
+    #[serde(skip)]
+    pub info: Option<AccessoryInfo>,
+    /// Persistent device settings, loaded on demand; not persisted.
+    #[serde(skip)]
+    pub device_settings: Option<LightSettings>,
 }
 
-impl Default for MyAppSettings {
+impl Default for LightConfig {
     fn default() -> Self {
         Self {
-            ip_address: "192.168.178.21".to_owned(),
+            id: new_light_id(),
+            name: "Key Light".to_owned(),
+            serial_number: String::new(),
+            hostname: String::new(),
+            ip_address: String::new(),
             port: 9123,
-            check_interval: 500,
+            index: 0,
             brightness: 100,
             temperature: 5000,
             light_on: false,
+            info: None,
+            device_settings: None,
         }
     }
 }
 
-/// Loads the application settings from the registry.
-/// Returns default settings if the registry key or values are missing.
-///
-/// # Example
-/// ```
-/// let settings = load_app_settings();
-/// ```
-pub fn load_app_settings() -> MyAppSettings {
-    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    let key_path = format!("Software\\{}", APPNAME);
-    if let Ok(key) = hkcu.open_subkey_with_flags(key_path, KEY_READ) {
-        let default = MyAppSettings::default();
-        let ip: String = key.get_value("IP").unwrap_or_else(|_| default.ip_address);
-        let port: u16 = key
-            .get_value::<u32, _>("Port")
-            .unwrap_or(default.port as u32) as u16;
-        let interval: u32 = key.get_value("Interval").unwrap_or(default.check_interval);
-        let (light_on, brightness, temperature) =
-            light::get_state(&ip, port).unwrap_or((false, default.brightness, default.temperature));
-        MyAppSettings {
-            ip_address: ip,
-            port,
-            check_interval: interval,
-            brightness: brightness,
-            temperature: temperature,
-            light_on: light_on,
+/// What the lights do when the camera is released.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OffPolicy {
+    /// Turn the lights off.
+    #[default]
+    Off,
+    /// Return to the on/brightness/temperature the lights had before the call.
+    Restore,
+}
+
+impl OffPolicy {
+    pub const ALL: [OffPolicy; 2] = [OffPolicy::Off, OffPolicy::Restore];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            OffPolicy::Off => "Turn off",
+            OffPolicy::Restore => "Restore previous state",
         }
-    } else {
-        MyAppSettings::default()
     }
 }
 
-/// Saves the provided application settings to the registry.
-///
-/// # Errors
-///
-/// Returns an error if the registry operation fails.
-pub fn save_app_settings(settings: MyAppSettings) -> Result<(), Box<dyn std::error::Error>> {
-    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    let (key, _) = hkcu.create_subkey(format!("Software\\{}", APPNAME))?;
-    key.set_value("IP", &settings.ip_address)?;
-    key.set_value("Port", &(settings.port as u32))?;
-    key.set_value("Interval", &settings.check_interval)?;
-    Ok(())
+/// Shortest check interval (ms); shorter ones would keep the detector busy.
+pub const MIN_CHECK_INTERVAL: u32 = 100;
+
+/// Application settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MyAppSettings {
+    pub lights: Vec<LightConfig>,
+    /// How long (ms) the detector waits for a change before it checks again;
+    /// at least [`MIN_CHECK_INTERVAL`].
+    pub check_interval: u32,
+    /// IPv4 CIDR probed by the subnet scan, e.g. `192.168.1.0/24`.
+    pub scan_range: String,
+    /// Device activity that switches the lights on.
+    pub trigger: Trigger,
+    /// Which applications may switch the lights on.
+    pub app_rules: AppRules,
+    /// How long (ms) the camera must be in use before the lights turn on.
+    pub on_delay: u32,
+    /// How long (ms) the camera must be idle before the lights turn off.
+    pub off_delay: u32,
+    /// What the lights do when the camera is released.
+    pub off_policy: OffPolicy,
 }
 
-pub fn is_autostart_enabled() -> bool {
-    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    let run = hkcu.open_subkey_with_flags(
-        "Software\\Microsoft\\Windows\\CurrentVersion\\Run",
-        winreg::enums::KEY_READ,
-    );
-    run.and_then(|key| key.get_value::<String, _>(APPNAME))
-        .is_ok()
+impl Default for MyAppSettings {
+    fn default() -> Self {
+        Self {
+            lights: Vec::new(),
+            check_interval: 500,
+            scan_range: String::new(),
+            trigger: Trigger::default(),
+            app_rules: AppRules::default(),
+            on_delay: 1000,
+            off_delay: 10000,
+            off_policy: OffPolicy::default(),
+        }
+    }
 }
 
-pub fn set_autostart(enable: bool) -> Result<(), Box<dyn std::error::Error>> {
-    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    let (key, _) = hkcu.create_subkey("Software\\Microsoft\\Windows\\CurrentVersion\\Run")?;
-    if enable {
-        let exe = std::env::current_exe()?.display().to_string();
-        key.set_value(APPNAME, &exe)?;
-    } else {
-        let _ = key.delete_value(APPNAME);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loaded_lights_get_distinct_ids() {
+        let json = r#"{"lights": [{"ip_address": "192.168.1.20"}, {"ip_address": "192.168.1.20", "index": 1}]}"#;
+        let settings: MyAppSettings = serde_json::from_str(json).unwrap();
+        assert_eq!(settings.lights.len(), 2);
+        assert_ne!(settings.lights[0].id, settings.lights[1].id);
     }
-    Ok(())
 }
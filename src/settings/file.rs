@@ -0,0 +1,73 @@
+// Helper functions to load and save settings in a JSON file under the XDG
+// config directory.
+use super::MyAppSettings;
+use crate::consts::*;
+use std::fs;
+use std::path::PathBuf;
+
+fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+fn settings_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(APPNAME).join("settings.json"))
+}
+
+fn autostart_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("autostart").join(format!("{}.desktop", APPNAME)))
+}
+
+/// Loads the application settings from `$XDG_CONFIG_HOME/KeyLightControl/settings.json`.
+/// Returns default settings if the file is missing or can't be parsed.
+///
+/// # Example
+/// ```
+/// let settings = load_app_settings();
+/// ```
+pub fn load_app_settings() -> MyAppSettings {
+    settings_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Saves the provided application settings to the settings file.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be written.
+pub fn save_app_settings(settings: &MyAppSettings) -> Result<(), Box<dyn std::error::Error>> {
+    let path = settings_path().ok_or("No config directory")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(settings)?)?;
+    Ok(())
+}
+
+pub fn is_autostart_enabled() -> bool {
+    autostart_path().is_some_and(|path| path.exists())
+}
+
+pub fn set_autostart(enable: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let path = autostart_path().ok_or("No config directory")?;
+    if enable {
+        let exe = std::env::current_exe()?.display().to_string();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            path,
+            format!(
+                "[Desktop Entry]\nType=Application\nName={}\nExec=\"{}\"\n",
+                APPNAME, exe
+            ),
+        )?;
+    } else {
+        let _ = fs::remove_file(path);
+    }
+    Ok(())
+}
@@ -0,0 +1,175 @@
+// Helper functions to load and save settings in the registry.
+use super::{LightConfig, MyAppSettings, OffPolicy};
+use crate::consts::*;
+use crate::utils::camera::{AppRules, Trigger};
+use winreg::enums::{HKEY_CURRENT_USER, KEY_READ};
+use winreg::RegKey;
+
+/// Loads the application settings from the registry.
+/// Returns default settings if the registry key or values are missing.
+///
+/// Lights are stored as subkeys of `Lights`, ordered by their `Order` value.
+/// Settings written by older versions (a single `IP`/`Port` pair) are
+/// migrated to one light.
+///
+/// # Example
+/// ```
+/// let settings = load_app_settings();
+/// ```
+pub fn load_app_settings() -> MyAppSettings {
+    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+    let key_path = format!("Software\\{}", APPNAME);
+    if let Ok(key) = hkcu.open_subkey_with_flags(key_path, KEY_READ) {
+        let default = MyAppSettings::default();
+        let interval: u32 = key.get_value("Interval").unwrap_or(default.check_interval);
+        let mut lights = load_lights(&key);
+        if lights.is_empty() {
+            lights = load_legacy_light(&key).into_iter().collect();
+        }
+        MyAppSettings {
+            lights,
+            check_interval: interval,
+            scan_range: key.get_value("ScanRange").unwrap_or(default.scan_range),
+            trigger: key
+                .get_value::<u32, _>("Trigger")
+                .ok()
+                .and_then(|i| Trigger::ALL.get(i as usize).copied())
+                .unwrap_or(default.trigger),
+            app_rules: AppRules {
+                include: key.get_value("IncludeApps").unwrap_or_default(),
+                exclude: key.get_value("ExcludeApps").unwrap_or_default(),
+            },
+            on_delay: key.get_value("OnDelay").unwrap_or(default.on_delay),
+            off_delay: key.get_value("OffDelay").unwrap_or(default.off_delay),
+            off_policy: key
+                .get_value::<u32, _>("OffPolicy")
+                .ok()
+                .and_then(|i| OffPolicy::ALL.get(i as usize).copied())
+                .unwrap_or(default.off_policy),
+        }
+    } else {
+        MyAppSettings::default()
+    }
+}
+
+fn load_lights(key: &RegKey) -> Vec<LightConfig> {
+    let Ok(lights_key) = key.open_subkey_with_flags("Lights", KEY_READ) else {
+        return Vec::new();
+    };
+    let mut lights: Vec<(u32, LightConfig)> = lights_key
+        .enum_keys()
+        .flatten()
+        .filter_map(|n| lights_key.open_subkey_with_flags(n, KEY_READ).ok())
+        .map(|k| (k.get_value("Order").unwrap_or(u32::MAX), load_light(&k)))
+        .collect();
+    lights.sort_by_key(|(order, _)| *order);
+    lights.into_iter().map(|(_, light)| light).collect()
+}
+
+fn load_light(key: &RegKey) -> LightConfig {
+    let default = LightConfig::default();
+    LightConfig {
+        id: default.id,
+        name: key.get_value("Name").unwrap_or(default.name),
+        serial_number: key.get_value("Serial").unwrap_or(default.serial_number),
+        hostname: key.get_value("Hostname").unwrap_or(default.hostname),
+        ip_address: key.get_value("IP").unwrap_or(default.ip_address),
+        port: key
+            .get_value::<u32, _>("Port")
+            .unwrap_or(default.port as u32) as u16,
+        index: key
+            .get_value::<u32, _>("Index")
+            .unwrap_or(default.index as u32) as usize,
+        brightness: key
+            .get_value::<u32, _>("Brightness")
+            .unwrap_or(default.brightness as u32) as u8,
+        temperature: key
+            .get_value::<u32, _>("Temperature")
+            .unwrap_or(default.temperature as u32) as u16,
+        light_on: false,
+        info: None,
+        device_settings: None,
+    }
+}
+
+fn load_legacy_light(key: &RegKey) -> Option<LightConfig> {
+    let ip: String = key.get_value("IP").ok()?;
+    let port = key.get_value::<u32, _>("Port").unwrap_or(9123) as u16;
+    Some(LightConfig {
+        ip_address: ip,
+        port,
+        ..LightConfig::default()
+    })
+}
+
+/// Saves the provided application settings to the registry.
+///
+/// # Errors
+///
+/// Returns an error if the registry operation fails.
+pub fn save_app_settings(settings: &MyAppSettings) -> Result<(), Box<dyn std::error::Error>> {
+    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+    let (key, _) = hkcu.create_subkey(format!("Software\\{}", APPNAME))?;
+    key.set_value("Interval", &settings.check_interval)?;
+    key.set_value("ScanRange", &settings.scan_range)?;
+    let trigger = Trigger::ALL.iter().position(|t| *t == settings.trigger).unwrap_or(0);
+    key.set_value("Trigger", &(trigger as u32))?;
+    key.set_value("OnDelay", &settings.on_delay)?;
+    key.set_value("OffDelay", &settings.off_delay)?;
+    let off_policy = OffPolicy::ALL.iter().position(|p| *p == settings.off_policy).unwrap_or(0);
+    key.set_value("OffPolicy", &(off_policy as u32))?;
+    // An empty REG_MULTI_SZ reads back as a single empty string, so drop it instead.
+    let rules = [
+        ("IncludeApps", &settings.app_rules.include),
+        ("ExcludeApps", &settings.app_rules.exclude),
+    ];
+    for (name, apps) in rules {
+        if apps.is_empty() {
+            let _ = key.delete_value(name);
+        } else {
+            key.set_value(name, apps)?;
+        }
+    }
+    let _ = key.delete_value("IP");
+    let _ = key.delete_value("Port");
+
+    // Rewrite the whole list so removed lights don't linger.
+    let _ = key.delete_subkey_all("Lights");
+    let (lights_key, _) = key.create_subkey("Lights")?;
+    for (i, light) in settings.lights.iter().enumerate() {
+        // Named by position: lights of a multi-light device share a serial number.
+        let (k, _) = lights_key.create_subkey(i.to_string())?;
+        k.set_value("Order", &(i as u32))?;
+        k.set_value("Name", &light.name)?;
+        k.set_value("Serial", &light.serial_number)?;
+        k.set_value("Hostname", &light.hostname)?;
+        k.set_value("IP", &light.ip_address)?;
+        k.set_value("Port", &(light.port as u32))?;
+        k.set_value("Index", &(light.index as u32))?;
+        k.set_value("Brightness", &(light.brightness as u32))?;
+        k.set_value("Temperature", &(light.temperature as u32))?;
+    }
+    Ok(())
+}
+
+pub fn is_autostart_enabled() -> bool {
+    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+    let run = hkcu.open_subkey_with_flags(
+        "Software\\Microsoft\\Windows\\CurrentVersion\\Run",
+        winreg::enums::KEY_READ,
+    );
+    run.and_then(|key| key.get_value::<String, _>(APPNAME))
+        .is_ok()
+}
+
+pub fn set_autostart(enable: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+    let (key, _) = hkcu.create_subkey("Software\\Microsoft\\Windows\\CurrentVersion\\Run")?;
+    if enable {
+        let exe = std::env::current_exe()?.display().to_string();
+        key.set_value(APPNAME, &exe)?;
+    } else {
+        let _ = key.delete_value(APPNAME);
+    }
+    Ok(())
+}
@@ -1,231 +1,774 @@
 // src/ui.rs
 
-use crate::background::{self, BackgroundCommand};
+use crate::background::{self, BackgroundCommand, EventKind, EventSender, Mode, WorkerEvent};
 use crate::settings::*;
-use crate::utils::light;
+use crate::utils::camera::{self, Trigger};
+use crate::utils::discovery::DiscoveredLight;
+use crate::utils::light::{self, LightSettings};
 use eframe::egui;
-use std::sync::mpsc::{Receiver, Sender};
+use std::collections::HashMap;
+use std::sync::mpsc::Receiver;
 use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 pub struct MyApp {
     pub settings: MyAppSettings,
-    pub cmd_tx: Sender<BackgroundCommand>,
-    pub log_rx: Receiver<String>,
+    pub cmd_tx: UnboundedSender<BackgroundCommand>,
+    pub event_rx: Receiver<WorkerEvent>,
     pub last_log: Option<String>,
+    pub status: Status,
+    /// Mode of the running worker.
+    pub mode: Mode,
+    /// Applications the worker last saw using the watched device.
+    pub active_apps: Vec<String>,
+    /// App rules as typed, see [`app_list_edit`].
+    pub include_text: String,
+    pub exclude_text: String,
     pub worker_handle: Option<JoinHandle<()>>,
     pub first_run: bool,
     pub auto_start: bool,
+    pub discovered: Vec<DiscoveredLight>,
+    /// Whether the worker is looking for lights.
+    pub discovering: bool,
 }
 
-impl Default for MyApp {
-    fn default() -> Self {
+impl MyApp {
+    /// Loads the settings and starts the worker, which repaints `ctx` when it
+    /// has news for the UI.
+    pub fn new(ctx: &egui::Context) -> Self {
         let settings: MyAppSettings = load_app_settings();
-        let (cmd_tx, cmd_rx) = std::sync::mpsc::channel();
-        let (log_tx, log_rx) = std::sync::mpsc::channel();
-        let (light_on, brightness, temperature) = light::get_state(
-            &settings.ip_address,
-            settings.port,
-        )
-        .unwrap_or((settings.light_on, settings.brightness, settings.temperature));
-        let handle = spawn_worker(
-            settings.ip_address.clone(),
-            settings.port,
-            settings.check_interval,
-            cmd_rx,
-            log_tx,
-            brightness,
-            temperature,
-        );
+        let (cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (event_tx, event_rx) = std::sync::mpsc::channel();
+        let handle = spawn_worker(&settings, cmd_rx, EventSender::new(event_tx, ctx.clone()));
 
         let auto = is_autostart_enabled();
+        let include_text = settings.app_rules.include.join(", ");
+        let exclude_text = settings.app_rules.exclude.join(", ");
 
         Self {
-            settings: MyAppSettings {
-                light_on,
-                brightness,
-                temperature,
-                ..settings
-            },
+            settings,
             cmd_tx,
-            log_rx,
+            event_rx,
             last_log: None,
+            status: Status::default(),
+            mode: Mode::Auto,
+            active_apps: Vec::new(),
+            include_text,
+            exclude_text,
             worker_handle: Some(handle),
             first_run: true,
             auto_start: auto,
+            discovered: Vec::new(),
+            discovering: false,
+        }
+    }
+}
+
+/// What the status panel shows, built from worker events.
+#[derive(Debug, Default)]
+pub struct Status {
+    pub camera: Option<CameraStatus>,
+    pub lights: HashMap<LightId, LightStatus>,
+    pub last_error: Option<(SystemTime, String)>,
+    pub config_applied: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+pub struct CameraStatus {
+    pub active: bool,
+    /// Applications that switched the lights on.
+    pub apps: Vec<String>,
+    pub since: SystemTime,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LightStatus {
+    /// Whether the light is on; `None` until the worker has reached it.
+    pub on: Option<bool>,
+    pub unreachable: bool,
+    pub since: Option<SystemTime>,
+}
+
+impl Status {
+    fn light_mut(&mut self, light: LightId) -> &mut LightStatus {
+        self.lights.entry(light).or_default()
+    }
+}
+
+impl MyApp {
+    fn handle_event(&mut self, event: WorkerEvent) {
+        let time = event.time;
+        // Drop events about lights that were removed, or pointed at another
+        // device, after the worker sent them.
+        if let Some(light) = event.kind.light() {
+            if !self.settings.lights.iter().any(|l| l.id == light) {
+                return;
+            }
+        }
+        let name = |light: LightId| {
+            self.settings
+                .lights
+                .iter()
+                .find(|l| l.id == light)
+                .map_or_else(String::new, |l| l.name.clone())
+        };
+        match event.kind {
+            EventKind::CameraStateChanged { active, apps } => {
+                self.status.camera = Some(CameraStatus {
+                    active,
+                    apps,
+                    since: time,
+                });
+            }
+            EventKind::ActiveApps(apps) => self.active_apps = apps,
+            EventKind::ModeChanged(mode) => self.mode = mode,
+            EventKind::LightStateChanged { light, state } => {
+                if let Some(l) = find_light(&mut self.settings.lights, light) {
+                    l.light_on = state.is_on();
+                    l.brightness = state.brightness;
+                    l.temperature = state.kelvin();
+                }
+                let status = self.status.light_mut(light);
+                if status.on != Some(state.is_on()) || status.unreachable {
+                    status.since = Some(time);
+                }
+                status.on = Some(state.is_on());
+                status.unreachable = false;
+            }
+            EventKind::DeviceUnreachable { light, error } => {
+                self.status.last_error = Some((time, format!("{} unreachable: {}", name(light), error)));
+                let status = self.status.light_mut(light);
+                if !status.unreachable {
+                    status.since = Some(time);
+                }
+                status.unreachable = true;
+            }
+            EventKind::AccessoryInfo { light, info } => {
+                let mut learned = false;
+                if let Some(l) = find_light(&mut self.settings.lights, light) {
+                    if l.serial_number.is_empty() && !info.serial_number.is_empty() {
+                        l.serial_number = info.serial_number.clone();
+                        learned = true;
+                    }
+                    l.info = Some(info);
+                }
+                // Remember the serial number so the light can be found again.
+                if learned {
+                    let _ = save_app_settings(&self.settings);
+                }
+            }
+            EventKind::DeviceSettings { light, settings } => {
+                if let Some(l) = find_light(&mut self.settings.lights, light) {
+                    l.device_settings = Some(settings);
+                }
+            }
+            EventKind::Discovered(found) => {
+                self.discovering = false;
+                match found {
+                    Ok(found) => {
+                        self.last_log = Some(format!("Discovered {} light(s)", found.len()));
+                        self.discovered = found;
+                    }
+                    Err(e) => self.last_log = Some(format!("Discovery failed: {}", e)),
+                }
+            }
+            EventKind::Relocated {
+                serial_number,
+                ip_address,
+            } => {
+                // The worker already uses the new address, just remember it.
+                for light in &mut self.settings.lights {
+                    if light.serial_number == serial_number {
+                        light.ip_address = ip_address.clone();
+                    }
+                }
+                self.last_log = Some(format!("Light {} moved to {}", serial_number, ip_address));
+                let _ = save_app_settings(&self.settings);
+            }
+            EventKind::DetectionFailed(error) => {
+                self.status.last_error = Some((time, format!("Failed to check device activity: {}", error)));
+            }
+            EventKind::ConfigApplied => self.status.config_applied = Some(time),
+            EventKind::Stopped => self.last_log = Some("Stopped camera control".to_owned()),
+        }
+    }
+}
+
+impl Drop for MyApp {
+    fn drop(&mut self) {
+        let _ = self.cmd_tx.send(BackgroundCommand::Stop);
+        if let Some(h) = self.worker_handle.take() {
+            let _ = h.join();
         }
     }
 }
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if self.first_run {
+        // Only Windows has a tray icon to bring the window back.
+        if self.first_run && cfg!(windows) {
             ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
             self.first_run = false;
         }
 
-        while let Ok(line) = self.log_rx.try_recv() {
-            self.last_log = Some(line);
+        while let Ok(event) = self.event_rx.try_recv() {
+            self.handle_event(event);
+        }
+
+        // Worker events repaint the UI as they come in; this only keeps the
+        // "… ago" labels of the status panel current while the window shows.
+        if !ctx.input(|i| i.viewport().minimized.unwrap_or(false)) {
+            ctx.request_repaint_after(Duration::from_secs(1));
         }
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            let mut restart = false;
+            let mut config_changed = false;
+            status_panel(ui, &self.status, &self.settings.lights);
             ui.horizontal(|ui| {
-                ui.label("IP address: ");
-                if ui
-                    .text_edit_singleline(&mut self.settings.ip_address)
-                    .changed()
-                {
-                    self.settings.ip_address = self.settings.ip_address.trim().to_string();
-                    restart = true;
+                ui.label("Mode: ");
+                for mode in Mode::ALL {
+                    if ui.selectable_value(&mut self.mode, mode, mode.label()).changed() {
+                        let _ = self.cmd_tx.send(BackgroundCommand::SetMode(mode));
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Interval (ms): ");
+                let interval = egui::DragValue::new(&mut self.settings.check_interval)
+                    .range(MIN_CHECK_INTERVAL..=60_000);
+                if ui.add(interval).changed() {
+                    config_changed = true;
                 }
             });
             ui.horizontal(|ui| {
-                ui.label("Port: ");
-                let mut s = self.settings.port.to_string();
+                ui.label("On delay (ms): ");
+                let mut s = self.settings.on_delay.to_string();
                 if ui.text_edit_singleline(&mut s).changed() {
-                    if let Ok(p) = s.parse() {
-                        self.settings.port = p;
-                        restart = true;
+                    if let Ok(i) = s.parse() {
+                        self.settings.on_delay = i;
+                        config_changed = true;
                     }
                 }
             });
             ui.horizontal(|ui| {
-                ui.label("Interval (ms): ");
-                let mut s = self.settings.check_interval.to_string();
+                ui.label("Off delay (ms): ");
+                let mut s = self.settings.off_delay.to_string();
                 if ui.text_edit_singleline(&mut s).changed() {
                     if let Ok(i) = s.parse() {
-                        self.settings.check_interval = i;
-                        restart = true;
+                        self.settings.off_delay = i;
+                        config_changed = true;
                     }
                 }
             });
+            ui.horizontal(|ui| {
+                ui.label("Turn lights on with: ");
+                egui::ComboBox::from_id_salt("trigger")
+                    .selected_text(self.settings.trigger.label())
+                    .show_ui(ui, |ui| {
+                        for trigger in Trigger::ALL {
+                            if ui
+                                .selectable_value(&mut self.settings.trigger, trigger, trigger.label())
+                                .changed()
+                            {
+                                config_changed = true;
+                            }
+                        }
+                    });
+            });
+            ui.horizontal(|ui| {
+                ui.label("When the camera stops: ");
+                egui::ComboBox::from_id_salt("off_policy")
+                    .selected_text(self.settings.off_policy.label())
+                    .show_ui(ui, |ui| {
+                        for policy in OffPolicy::ALL {
+                            if ui
+                                .selectable_value(&mut self.settings.off_policy, policy, policy.label())
+                                .changed()
+                            {
+                                config_changed = true;
+                            }
+                        }
+                    });
+            });
+
+            egui::CollapsingHeader::new("App rules").show(ui, |ui| {
+                let rules = &mut self.settings.app_rules;
+                if app_list_edit(ui, "Only these apps: ", &mut self.include_text, &mut rules.include) {
+                    config_changed = true;
+                }
+                if app_list_edit(ui, "Ignore these apps: ", &mut self.exclude_text, &mut rules.exclude) {
+                    config_changed = true;
+                }
+            });
 
             ui.separator();
             if ui
-                .checkbox(&mut self.auto_start, "Start with Windows")
+                .checkbox(
+                    &mut self.auto_start,
+                    if cfg!(windows) {
+                        "Start with Windows"
+                    } else {
+                        "Start on login"
+                    },
+                )
                 .changed()
             {
                 set_autostart(self.auto_start)
                     .unwrap_or_else(|e| eprintln!("Registry error: {:?}", e));
             }
 
-            ui.separator();
-            if ui.button("Toggle Light On/Off").clicked() {
-                if !self.settings.light_on {
-                    if let Err(e) = self.cmd_tx.send(BackgroundCommand::Stop) {
-                        eprintln!("Failed to send command: {}", e);
+            let mut remove = None;
+            for (i, light) in self.settings.lights.iter_mut().enumerate() {
+                ui.separator();
+                ui.push_id(i, |ui| {
+                    if light_row(ui, light, &self.cmd_tx, &mut self.mode, &mut config_changed) {
+                        remove = Some(i);
                     }
-                } else {
-                    restart = true;
-                }
-                if let Err(e) = light::set_state(
-                    !self.settings.light_on,
-                    &self.settings.ip_address,
-                    self.settings.port,
-                    self.settings.brightness,
-                    self.settings.temperature,
-                ) {
-                    eprintln!("Failed to toggle light: {}", e);
-                } else {
-                    // update light_on state if successful
-                    self.settings.light_on = !self.settings.light_on;
-                }
+                });
             }
-
-            if ui
-                .add(
-                    egui::Slider::new(&mut self.settings.brightness, 0..=100)
-                        .text("Brightness")
-                        .step_by(1.0),
-                )
-                .changed()
-            {
-                if let Err(e) = light::set_state(
-                    self.settings.light_on,
-                    &self.settings.ip_address,
-                    self.settings.port,
-                    self.settings.brightness,
-                    self.settings.temperature,
-                ) {
-                    eprintln!("Failed to update brightness: {}", e);
-                }
+            if let Some(i) = remove {
+                self.settings.lights.remove(i);
+                config_changed = true;
             }
 
-            if ui
-                .add(
-                    egui::Slider::new(&mut self.settings.temperature, 2900..=7000)
-                        .text("Temperature Kelvin")
-                        .step_by(50.0),
-                )
-                .changed()
-            {
-                if let Err(e) = light::set_state(
-                    self.settings.light_on,
-                    &self.settings.ip_address,
-                    self.settings.port,
-                    self.settings.brightness,
-                    self.settings.temperature,
-                ) {
-                    eprintln!("Failed to update temperature: {}", e);
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Add light").clicked() {
+                    self.settings.lights.push(LightConfig::default());
+                    config_changed = true;
+                }
+                if ui.button("Refresh").clicked() {
+                    let _ = self.cmd_tx.send(BackgroundCommand::RefreshState);
+                }
+                if ui
+                    .add_enabled(!self.discovering, egui::Button::new("Discover lights"))
+                    .clicked()
+                {
+                    self.discovering = true;
+                    let _ = self.cmd_tx.send(BackgroundCommand::DiscoverLights);
+                }
+                if self.discovering {
+                    ui.spinner();
                 }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Subnet: ");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.settings.scan_range)
+                        .hint_text("192.168.1.0/24"),
+                );
+                if ui
+                    .add_enabled(!self.discovering, egui::Button::new("Scan subnet"))
+                    .clicked()
+                {
+                    let _ = save_app_settings(&self.settings);
+                    self.discovering = true;
+                    let range = self.settings.scan_range.clone();
+                    let _ = self.cmd_tx.send(BackgroundCommand::ScanSubnet(range));
+                }
+            });
+            if let Some(found) = discovered_list(ui, &self.discovered, &self.settings.lights) {
+                self.settings.lights.push(LightConfig {
+                    name: found.name,
+                    serial_number: found.serial_number,
+                    hostname: found.hostname,
+                    ip_address: found.ip_address,
+                    port: found.port,
+                    ..LightConfig::default()
+                });
+                config_changed = true;
             }
 
             ui.separator();
 
+            if !self.active_apps.is_empty() {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("In use by:");
+                    for app in &self.active_apps {
+                        ui.label(app);
+                        if ui.small_button("Ignore").clicked() {
+                            self.settings.app_rules.exclude.push(app.clone());
+                            self.exclude_text = self.settings.app_rules.exclude.join(", ");
+                            config_changed = true;
+                        }
+                    }
+                });
+            }
             ui.label(self.last_log.as_deref().unwrap_or(""));
 
-            if restart {
-                let _ = save_app_settings(MyAppSettings {
-                    ip_address: self.settings.ip_address.clone(),
-                    port: self.settings.port,
-                    check_interval: self.settings.check_interval,
-                    brightness: self.settings.brightness,
-                    temperature: self.settings.temperature,
-                    light_on: self.settings.light_on,
-                });
+            if config_changed {
+                let lights = &self.settings.lights;
+                self.status.lights.retain(|id, _| lights.iter().any(|l| l.id == *id));
+                let _ = save_app_settings(&self.settings);
+                let _ = self
+                    .cmd_tx
+                    .send(BackgroundCommand::UpdateConfig(self.settings.clone()));
+            }
+        });
+    }
+}
+
+/// Draws the controls for a single light.
+/// Returns `true` if the user asked to remove the light.
+fn light_row(
+    ui: &mut egui::Ui,
+    light: &mut LightConfig,
+    cmd_tx: &UnboundedSender<BackgroundCommand>,
+    mode: &mut Mode,
+    config_changed: &mut bool,
+) -> bool {
+    let mut remove = false;
+    ui.horizontal(|ui| {
+        ui.label("Host or IP address: ");
+        // Each change makes the worker reconnect, so wait until it's typed.
+        if committed_text_edit(ui, "address", &mut light.ip_address) {
+            // The address may now point at another device; forget the old
+            // one so it isn't looked up by serial number and moved back.
+            light.id = new_light_id();
+            light.serial_number.clear();
+            light.hostname.clear();
+            light.info = None;
+            *config_changed = true;
+        }
+        if ui.button("Identify").clicked() {
+            if let Err(e) = cmd_tx.send(BackgroundCommand::Identify { light: light.id }) {
+                eprintln!("Failed to send command: {}", e);
+            }
+        }
+        remove = ui.button("Remove").clicked();
+    });
+    ui.horizontal(|ui| {
+        ui.label("Name: ");
+        if ui.text_edit_singleline(&mut light.name).changed() {
+            *config_changed = true;
+        }
+        if ui.button("Rename light").clicked() {
+            let cmd = BackgroundCommand::Rename {
+                light: light.id,
+                name: light.name.clone(),
+            };
+            if let Err(e) = cmd_tx.send(cmd) {
+                eprintln!("Failed to send command: {}", e);
+            }
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("Port: ");
+        let mut s = light.port.to_string();
+        if committed_text_edit(ui, "port", &mut s) {
+            if let Ok(p) = s.parse() {
+                light.port = p;
+                light.id = new_light_id();
+                *config_changed = true;
+            }
+        }
+        ui.label("Light index: ");
+        if ui
+            .add(egui::DragValue::new(&mut light.index).range(0..=7))
+            .changed()
+        {
+            light.id = new_light_id();
+            *config_changed = true;
+        }
+    });
+
+    egui::CollapsingHeader::new("Device info").show(ui, |ui| match &light.info {
+        Some(info) => {
+            egui::Grid::new("device_info").num_columns(2).show(ui, |ui| {
+                ui.label("Product:");
+                ui.label(&info.product_name);
+                ui.end_row();
+                ui.label("Display name:");
+                ui.label(&info.display_name);
+                ui.end_row();
+                ui.label("Serial number:");
+                ui.label(&info.serial_number);
+                ui.end_row();
+                ui.label("Firmware:");
+                ui.label(format!(
+                    "{} (build {})",
+                    info.firmware_version, info.firmware_build_number
+                ));
+                ui.end_row();
+            });
+        }
+        None => {
+            ui.label("Device info unavailable");
+        }
+    });
+
+    egui::CollapsingHeader::new("Device settings").show(ui, |ui| {
+        device_settings_section(ui, light, cmd_tx);
+    });
+
+    if ui.button("Toggle Light On/Off").clicked() {
+        // Keep automation from undoing the toggle until the next call.
+        if *mode == Mode::Auto {
+            *mode = Mode::Paused;
+            if let Err(e) = cmd_tx.send(BackgroundCommand::SetMode(Mode::Paused)) {
+                eprintln!("Failed to send command: {}", e);
+            }
+        }
+        let cmd = BackgroundCommand::SetPower {
+            light: light.id,
+            on: !light.light_on,
+        };
+        if let Err(e) = cmd_tx.send(cmd) {
+            eprintln!("Failed to send command: {}", e);
+        }
+    }
+
+    if ui
+        .add(
+            egui::Slider::new(&mut light.brightness, 0..=100)
+                .text("Brightness")
+                .step_by(1.0),
+        )
+        .changed()
+    {
+        let cmd = BackgroundCommand::SetBrightness {
+            light: light.id,
+            brightness: light.brightness,
+        };
+        if let Err(e) = cmd_tx.send(cmd) {
+            eprintln!("Failed to send command: {}", e);
+        }
+    }
+
+    if ui
+        .add(
+            egui::Slider::new(&mut light.temperature, 2900..=7000)
+                .text("Temperature Kelvin")
+                .step_by(50.0),
+        )
+        .changed()
+    {
+        let cmd = BackgroundCommand::SetTemperature {
+            light: light.id,
+            temperature: light.temperature,
+        };
+        if let Err(e) = cmd_tx.send(cmd) {
+            eprintln!("Failed to send command: {}", e);
+        }
+    }
+    remove
+}
+
+/// Draws the editor for the persistent power-on and transition settings.
+fn device_settings_section(
+    ui: &mut egui::Ui,
+    light: &mut LightConfig,
+    cmd_tx: &UnboundedSender<BackgroundCommand>,
+) {
+    ui.horizontal(|ui| {
+        if ui.button("Load").clicked() {
+            if let Err(e) = cmd_tx.send(BackgroundCommand::LoadDeviceSettings { light: light.id }) {
+                eprintln!("Failed to send command: {}", e);
+            }
+        }
+        if let Some(settings) = light.device_settings {
+            if ui.button("Apply").clicked() {
+                let cmd = BackgroundCommand::ApplyDeviceSettings {
+                    light: light.id,
+                    settings,
+                };
+                if let Err(e) = cmd_tx.send(cmd) {
+                    eprintln!("Failed to send command: {}", e);
+                }
+            }
+        }
+    });
+
+    let Some(s) = light.device_settings.as_mut() else {
+        ui.label("Press \"Load\" to read the settings from the light");
+        return;
+    };
+    edit_device_settings(ui, s);
+}
+
+fn edit_device_settings(ui: &mut egui::Ui, s: &mut LightSettings) {
+    egui::ComboBox::from_label("Power-on behaviour")
+        .selected_text(if s.power_on_behavior == 2 {
+            "Use power-on values"
+        } else {
+            "Restore last state"
+        })
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut s.power_on_behavior, 1, "Restore last state");
+            ui.selectable_value(&mut s.power_on_behavior, 2, "Use power-on values");
+        });
+    ui.add(
+        egui::Slider::new(&mut s.power_on_brightness, 0..=100)
+            .text("Power-on brightness")
+            .step_by(1.0),
+    );
+    let mut kelvin = light::api_temp_to_kelvin(s.power_on_temperature);
+    if ui
+        .add(
+            egui::Slider::new(&mut kelvin, 2900..=7000)
+                .text("Power-on temperature Kelvin")
+                .step_by(50.0),
+        )
+        .changed()
+    {
+        s.power_on_temperature = light::kelvin_to_api_temp(kelvin);
+    }
+    ui.horizontal(|ui| {
+        ui.label("Switch-on (ms): ");
+        ui.add(egui::DragValue::new(&mut s.switch_on_duration_ms).range(0..=10_000));
+        ui.label("Switch-off (ms): ");
+        ui.add(egui::DragValue::new(&mut s.switch_off_duration_ms).range(0..=10_000));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Colour change (ms): ");
+        ui.add(egui::DragValue::new(&mut s.color_change_duration_ms).range(0..=10_000));
+    });
+}
+
+/// Shows the camera state, a live indicator per light and the last error.
+fn status_panel(ui: &mut egui::Ui, status: &Status, lights: &[LightConfig]) {
+    ui.group(|ui| {
+        ui.horizontal(|ui| match &status.camera {
+            Some(camera) => {
+                indicator(ui, if camera.active { egui::Color32::GREEN } else { egui::Color32::GRAY });
+                if camera.active {
+                    ui.label(format!("Camera in use by {}", camera.apps.join(", ")));
+                } else {
+                    ui.label("Camera idle");
+                }
+                ui.weak(ago(camera.since));
+            }
+            None => {
+                indicator(ui, egui::Color32::GRAY);
+                ui.label("Camera state unknown");
+            }
+        });
+        for light in lights {
+            let light_status = status.lights.get(&light.id).cloned().unwrap_or_default();
+            let (color, state) = match light_status {
+                LightStatus { unreachable: true, .. } => (egui::Color32::RED, "unreachable"),
+                LightStatus { on: Some(true), .. } => (egui::Color32::YELLOW, "on"),
+                LightStatus { on: Some(false), .. } => (egui::Color32::DARK_GRAY, "off"),
+                LightStatus { on: None, .. } => (egui::Color32::GRAY, "unknown"),
+            };
+            ui.horizontal(|ui| {
+                indicator(ui, color);
+                ui.label(format!("{}: {}", light.name, state));
+                if let Some(since) = light_status.since {
+                    ui.weak(ago(since));
+                }
+            });
+        }
+        if let Some((time, error)) = &status.last_error {
+            ui.horizontal(|ui| {
+                ui.colored_label(egui::Color32::RED, format!("Last error: {}", error));
+                ui.weak(ago(*time));
+            });
+        }
+        if let Some(time) = status.config_applied {
+            ui.weak(format!("Settings applied {}", ago(time)));
+        }
+    });
+}
+
+/// Draws a small filled circle.
+fn indicator(ui: &mut egui::Ui, color: egui::Color32) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(12.0, 12.0), egui::Sense::hover());
+    ui.painter().circle_filled(rect.center(), 5.0, color);
+}
+
+/// Formats how long ago `time` was, e.g. `5 s ago`.
+fn ago(time: SystemTime) -> String {
+    let secs = time.elapsed().unwrap_or_default().as_secs();
+    match secs {
+        0..=59 => format!("{} s ago", secs),
+        60..=3599 => format!("{} min ago", secs / 60),
+        _ => format!("{} h ago", secs / 3600),
+    }
+}
 
-                let _ = self.cmd_tx.send(BackgroundCommand::Stop);
-                if let Some(h) = self.worker_handle.take() {
-                    let _ = h.join();
-                }
-
-                self.last_log = None;
-
-                let (cmd_tx, cmd_rx) = std::sync::mpsc::channel();
-                let (log_tx, log_rx) = std::sync::mpsc::channel();
-                let ip = self.settings.ip_address.clone();
-                let port = self.settings.port;
-                let interval = self.settings.check_interval;
-                let (light_on, brightness, temperature) =
-                    light::get_state(&self.settings.ip_address, self.settings.port).unwrap_or((
-                        self.settings.light_on,
-                        self.settings.brightness,
-                        self.settings.temperature,
-                    ));
-                self.settings.light_on = light_on;
-                self.settings.brightness = brightness;
-                self.settings.temperature = temperature;
-                let handle =
-                    spawn_worker(ip, port, interval, cmd_rx, log_tx, brightness, temperature);
-
-                self.worker_handle = Some(handle);
-                self.cmd_tx = cmd_tx;
-                self.log_rx = log_rx;
+/// Lists discovered lights that aren't configured yet.
+/// Returns the light the user chose to add.
+fn discovered_list(
+    ui: &mut egui::Ui,
+    discovered: &[DiscoveredLight],
+    lights: &[LightConfig],
+) -> Option<DiscoveredLight> {
+    let mut chosen = None;
+    for found in discovered {
+        if lights
+            .iter()
+            .any(|l| l.ip_address == found.ip_address && l.port == found.port)
+        {
+            continue;
+        }
+        ui.horizontal(|ui| {
+            ui.label(format!("{} ({}:{})", found.name, found.ip_address, found.port));
+            if ui.button("Add").clicked() {
+                chosen = Some(found.clone());
             }
         });
     }
+    chosen
 }
 
 fn spawn_worker(
-    ip: String,
-    port: u16,
-    interval: u32,
-    cmd_rx: Receiver<BackgroundCommand>,
-    log_tx: Sender<String>,
-    brightness: u8,
-    temperature: u16,
+    settings: &MyAppSettings,
+    cmd_rx: UnboundedReceiver<BackgroundCommand>,
+    events: EventSender,
 ) -> JoinHandle<()> {
-    std::thread::spawn(move || {
-        background::run(ip, port, cmd_rx, log_tx, interval, brightness, temperature)
-    })
+    let detector = camera::detector_for(settings.trigger);
+    let settings = settings.clone();
+    std::thread::spawn(move || background::run(settings, detector, cmd_rx, events))
+}
+
+/// Edits a list of application names as comma-separated text. The text is
+/// kept in `text` while typing so that separators aren't eaten; `apps` is
+/// updated when the field loses focus. Returns `true` if `apps` changed.
+fn app_list_edit(ui: &mut egui::Ui, label: &str, text: &mut String, apps: &mut Vec<String>) -> bool {
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        ui.label(label);
+        let response = ui.add(egui::TextEdit::singleline(text).hint_text("Zoom.exe, Teams"));
+        if response.lost_focus() {
+            let parsed: Vec<String> = text
+                .split(',')
+                .map(str::trim)
+                .filter(|app| !app.is_empty())
+                .map(str::to_owned)
+                .collect();
+            changed = parsed != *apps;
+            *apps = parsed;
+            *text = apps.join(", ");
+        }
+    });
+    changed
+}
+
+/// Single-line text field that only updates `value` once editing is done,
+/// when the field loses focus or Enter is pressed. The text is kept in egui's
+/// memory while typing. Returns `true` if `value` changed.
+fn committed_text_edit(ui: &mut egui::Ui, id_salt: &str, value: &mut String) -> bool {
+    let id = ui.make_persistent_id(id_salt);
+    let mut text = ui
+        .data_mut(|d| d.get_temp::<String>(id))
+        .unwrap_or_else(|| value.clone());
+    let response = ui.text_edit_singleline(&mut text);
+    if response.lost_focus() {
+        ui.data_mut(|d| d.remove::<String>(id));
+        let text = text.trim();
+        if text != value {
+            *value = text.to_owned();
+            return true;
+        }
+    } else if response.has_focus() {
+        ui.data_mut(|d| d.insert_temp(id, text));
+    }
+    false
+}
+
+/// The configured light with `id`.
+fn find_light(lights: &mut [LightConfig], id: LightId) -> Option<&mut LightConfig> {
+    lights.iter_mut().find(|l| l.id == id)
 }
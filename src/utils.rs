@@ -1,3 +1,4 @@
 pub mod camera;
+pub mod discovery;
 pub mod light;
 pub mod icon;
\ No newline at end of file
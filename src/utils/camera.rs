@@ -1,26 +1,394 @@
+//! Camera and microphone activity detection.
+use serde::{Deserialize, Serialize};
+#[cfg(test)]
+use std::collections::VecDeque;
 use std::io;
-use winreg::{enums::HKEY_CURRENT_USER, RegKey};
-
-pub fn is_enabled() -> io::Result<bool> {
-    let key: &str = r"SOFTWARE\Microsoft\Windows\CurrentVersion\CapabilityAccessManager\ConsentStore\webcam\NonPackaged";
-    let hkcu: RegKey = RegKey::predef(HKEY_CURRENT_USER);
-
-    // Try to open the main key; if it doesn't exist, assume the camera is disabled
-    let res: RegKey = match hkcu.open_subkey(key) {
-        Ok(k) => k,
-        Err(_) => return Ok(false),
-    };
-
-    for subkey_name in res.enum_keys().flatten() {
-        let sub_path = format!(r"{}\{}", key, subkey_name);
-        if let Ok(subkey) = hkcu.open_subkey(&sub_path) {
-            if let Ok(time_stop) = subkey.get_value::<u64, _>("LastUsedTimeStop") {
-                if time_stop == 0 {
-                    println!("Camera access is enabled for {}", sub_path);
-                    return Ok(true);
+use std::thread;
+use std::time::Duration;
+
+// Platform-neutral so the Windows detection logic can run on a mock registry.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub mod consent;
+#[cfg(target_os = "linux")]
+pub mod procfs;
+#[cfg(target_os = "linux")]
+pub mod pulse;
+#[cfg(windows)]
+pub mod registry;
+
+/// Reports which applications currently use a device (camera or microphone).
+pub trait ActivityDetector {
+    /// Returns the names of the applications using the device, empty when
+    /// it is idle.
+    fn active_apps(&mut self) -> io::Result<Vec<String>>;
+
+    /// Blocks until the device may have been opened or closed, or `timeout`
+    /// has passed. Returns `false` if nothing changed, so the caller can skip
+    /// [`active_apps`](Self::active_apps).
+    ///
+    /// Detectors without change notifications sleep and report a change,
+    /// which makes the caller poll every `timeout`.
+    fn wait_for_change(&mut self, timeout: Duration) -> bool {
+        thread::sleep(timeout);
+        true
+    }
+}
+
+/// Which device activity switches the lights on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Trigger {
+    #[default]
+    Camera,
+    Microphone,
+    /// Camera or microphone.
+    Either,
+    /// Camera and microphone at the same time.
+    Both,
+}
+
+impl Trigger {
+    pub const ALL: [Trigger; 4] = [Trigger::Camera, Trigger::Microphone, Trigger::Either, Trigger::Both];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Trigger::Camera => "Camera",
+            Trigger::Microphone => "Microphone",
+            Trigger::Either => "Camera or microphone",
+            Trigger::Both => "Camera and microphone",
+        }
+    }
+}
+
+/// Decides which detected applications may switch the lights on.
+///
+/// Rules match application names case-insensitively, on any part of the
+/// name, so `zoom` matches `Zoom.exe`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppRules {
+    /// If not empty, only these applications trigger the lights.
+    pub include: Vec<String>,
+    /// Applications that never trigger the lights, e.g. Windows Hello.
+    pub exclude: Vec<String>,
+}
+
+impl AppRules {
+    pub fn allows(&self, app: &str) -> bool {
+        let app = app.to_lowercase();
+        let matches = |rule: &String| !rule.is_empty() && app.contains(&rule.to_lowercase());
+        (self.include.is_empty() || self.include.iter().any(matches)) && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Returns the camera detector for the platform the app runs on.
+pub fn camera_detector() -> Box<dyn ActivityDetector + Send> {
+    #[cfg(windows)]
+    let detector = registry::RegistryDetector::webcam();
+    #[cfg(target_os = "linux")]
+    let detector = procfs::ProcfsDetector::default();
+    #[cfg(not(any(windows, target_os = "linux")))]
+    let detector = Unsupported;
+    Box::new(detector)
+}
+
+/// Returns the microphone detector for the platform the app runs on.
+pub fn microphone_detector() -> Box<dyn ActivityDetector + Send> {
+    #[cfg(windows)]
+    let detector = registry::RegistryDetector::microphone();
+    #[cfg(target_os = "linux")]
+    let detector = pulse::PulseDetector;
+    #[cfg(not(any(windows, target_os = "linux")))]
+    let detector = Unsupported;
+    Box::new(detector)
+}
+
+/// Returns a detector that reports activity according to `trigger`.
+pub fn detector_for(trigger: Trigger) -> Box<dyn ActivityDetector + Send> {
+    match trigger {
+        Trigger::Camera => camera_detector(),
+        Trigger::Microphone => microphone_detector(),
+        Trigger::Either => Box::new(Combined::new(vec![camera_detector(), microphone_detector()], false)),
+        Trigger::Both => Box::new(Combined::new(vec![camera_detector(), microphone_detector()], true)),
+    }
+}
+
+/// Combines several detectors; active if any (or all) of them are. Reports
+/// the applications of every detector that is active.
+///
+/// Each detector's last reading is kept, and only the detectors that
+/// reported a change are read again, so one that has to be polled doesn't
+/// make the others rescan every time.
+///
+/// When any one detector is enough, those that fail are skipped, so that a
+/// missing microphone backend doesn't stop camera detection. The reading
+/// only fails if every detector does.
+pub struct Combined {
+    detectors: Vec<Box<dyn ActivityDetector + Send>>,
+    require_all: bool,
+    /// Last successful reading of each detector; `None` until there is one,
+    /// or after it failed.
+    readings: Vec<Option<Vec<String>>>,
+    /// Whether each detector may have changed since it was last read.
+    changed: Vec<bool>,
+}
+
+impl Combined {
+    pub fn new(detectors: Vec<Box<dyn ActivityDetector + Send>>, require_all: bool) -> Self {
+        let count = detectors.len();
+        Self {
+            detectors,
+            require_all,
+            readings: vec![None; count],
+            changed: vec![true; count],
+        }
+    }
+}
+
+impl ActivityDetector for Combined {
+    fn active_apps(&mut self) -> io::Result<Vec<String>> {
+        let mut error = None;
+        for (i, detector) in self.detectors.iter_mut().enumerate() {
+            if !self.changed[i] && self.readings[i].is_some() {
+                continue;
+            }
+            match detector.active_apps() {
+                Ok(apps) => {
+                    self.readings[i] = Some(apps);
+                    self.changed[i] = false;
                 }
+                Err(e) => {
+                    self.readings[i] = None;
+                    if self.require_all {
+                        return Err(e);
+                    }
+                    error = Some(e);
+                }
+            }
+        }
+        let readings: Vec<&Vec<String>> = self.readings.iter().flatten().collect();
+        if let Some(e) = error.filter(|_| readings.is_empty()) {
+            return Err(e);
+        }
+        if self.require_all && readings.iter().any(|apps| apps.is_empty()) {
+            return Ok(Vec::new());
+        }
+        let mut apps: Vec<String> = Vec::new();
+        for app in readings.into_iter().flatten() {
+            if !apps.contains(app) {
+                apps.push(app.clone());
             }
         }
+        Ok(apps)
+    }
+
+    /// Waits on every detector in turn, each for its share of `timeout`, so
+    /// that all of them keep their notifications armed.
+    fn wait_for_change(&mut self, timeout: Duration) -> bool {
+        let share = timeout / self.detectors.len().max(1) as u32;
+        for (changed, detector) in self.changed.iter_mut().zip(&mut self.detectors) {
+            *changed |= detector.wait_for_change(share);
+        }
+        self.changed.iter().any(|&changed| changed)
+    }
+}
+
+/// Replays a fixed sequence of readings, repeating the last one once the
+/// script runs out. Lets the worker's on/off logic run without a camera.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct ScriptedDetector {
+    readings: VecDeque<Vec<String>>,
+    last: Vec<String>,
+}
+
+#[cfg(test)]
+impl ScriptedDetector {
+    /// Scripts plain on/off readings; active readings report a single
+    /// `scripted` application.
+    pub fn new(readings: impl IntoIterator<Item = bool>) -> Self {
+        Self::with_apps(
+            readings
+                .into_iter()
+                .map(|active| if active { vec!["scripted".to_owned()] } else { Vec::new() }),
+        )
+    }
+
+    /// Scripts the applications reported by each reading.
+    pub fn with_apps(readings: impl IntoIterator<Item = Vec<String>>) -> Self {
+        Self {
+            readings: readings.into_iter().collect(),
+            last: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ActivityDetector for ScriptedDetector {
+    fn active_apps(&mut self) -> io::Result<Vec<String>> {
+        if let Some(apps) = self.readings.pop_front() {
+            self.last = apps;
+        }
+        Ok(self.last.clone())
+    }
+
+    /// Reports a change while scripted readings are left.
+    fn wait_for_change(&mut self, _timeout: Duration) -> bool {
+        !self.readings.is_empty()
+    }
+}
+
+/// Fallback for platforms without a backend; the device is never active.
+#[cfg(not(any(windows, target_os = "linux")))]
+struct Unsupported;
+
+#[cfg(not(any(windows, target_os = "linux")))]
+impl ActivityDetector for Unsupported {
+    fn active_apps(&mut self) -> io::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A backend that isn't available, like `pactl` not being installed.
+    struct Failing;
+
+    impl ActivityDetector for Failing {
+        fn active_apps(&mut self) -> io::Result<Vec<String>> {
+            Err(io::ErrorKind::NotFound.into())
+        }
+    }
+
+    fn apps(names: &[&str]) -> Vec<String> {
+        names.iter().map(|&name| name.to_owned()).collect()
+    }
+
+    fn rules(include: &[&str], exclude: &[&str]) -> AppRules {
+        AppRules {
+            include: apps(include),
+            exclude: apps(exclude),
+        }
+    }
+
+    #[test]
+    fn empty_include_allows_every_app() {
+        let rules = rules(&[], &[]);
+        assert!(rules.allows("Zoom.exe"));
+        assert!(rules.allows("anything"));
+    }
+
+    #[test]
+    fn include_limits_allowed_apps() {
+        let rules = rules(&["zoom", "teams"], &[]);
+        assert!(rules.allows("Teams.exe"));
+        assert!(!rules.allows("Discord.exe"));
+    }
+
+    #[test]
+    fn exclude_overrides_include() {
+        let zoom_but_not_client = rules(&["Zoom"], &["zoom.exe"]);
+        assert!(!zoom_but_not_client.allows("Zoom.exe"));
+        assert!(zoom_but_not_client.allows("ZoomIt64"));
+        assert!(!rules(&[], &["WindowsHello"]).allows("Microsoft.WindowsHello"));
+    }
+
+    #[test]
+    fn rules_match_any_part_of_name_ignoring_case() {
+        assert!(rules(&["zoom"], &[]).allows("Zoom.exe"));
+        assert!(rules(&["ZOOM"], &[]).allows("C:\\Program Files\\zoom\\bin\\zoom.exe"));
+        // Blank rules match nothing.
+        assert!(!rules(&[""], &[]).allows("Zoom.exe"));
+    }
+
+    #[test]
+    fn scripted_detector_repeats_last_reading() {
+        let mut detector = ScriptedDetector::new([false, true]);
+        assert!(detector.active_apps().unwrap().is_empty());
+        assert_eq!(detector.active_apps().unwrap(), ["scripted"]);
+        assert_eq!(detector.active_apps().unwrap(), ["scripted"]);
+    }
+
+    /// A detector without news: never reports a change.
+    struct Quiet(ScriptedDetector);
+
+    impl ActivityDetector for Quiet {
+        fn active_apps(&mut self) -> io::Result<Vec<String>> {
+            self.0.active_apps()
+        }
+
+        fn wait_for_change(&mut self, _timeout: Duration) -> bool {
+            false
+        }
+    }
+
+    /// Waits for a change like the detector thread does, then reads.
+    fn next(combined: &mut Combined) -> io::Result<Vec<String>> {
+        combined.wait_for_change(Duration::ZERO);
+        combined.active_apps()
+    }
+
+    #[test]
+    fn either_reports_apps_of_any_detector() {
+        let mut combined = Combined::new(
+            vec![
+                Box::new(ScriptedDetector::with_apps([apps(&["Zoom"]), apps(&[]), apps(&["Zoom"])])),
+                Box::new(ScriptedDetector::with_apps([apps(&["Zoom", "Discord"]), apps(&[]), apps(&[])])),
+            ],
+            false,
+        );
+        assert_eq!(combined.active_apps().unwrap(), ["Zoom", "Discord"]);
+        assert!(next(&mut combined).unwrap().is_empty());
+        assert_eq!(next(&mut combined).unwrap(), ["Zoom"]);
+    }
+
+    #[test]
+    fn both_requires_every_detector() {
+        let mut combined = Combined::new(
+            vec![
+                Box::new(ScriptedDetector::with_apps([apps(&["Zoom"]), apps(&["Zoom"])])),
+                Box::new(ScriptedDetector::with_apps([apps(&[]), apps(&["Discord"])])),
+            ],
+            true,
+        );
+        assert!(combined.active_apps().unwrap().is_empty());
+        assert_eq!(next(&mut combined).unwrap(), ["Zoom", "Discord"]);
+    }
+
+    #[test]
+    fn rereads_only_detectors_that_changed() {
+        let mut combined = Combined::new(
+            vec![
+                Box::new(Quiet(ScriptedDetector::with_apps([apps(&["Zoom"]), apps(&[])]))),
+                Box::new(ScriptedDetector::with_apps([apps(&[]), apps(&["Discord"])])),
+            ],
+            false,
+        );
+        assert_eq!(combined.active_apps().unwrap(), ["Zoom"]);
+        assert!(combined.wait_for_change(Duration::ZERO));
+        assert_eq!(combined.active_apps().unwrap(), ["Zoom", "Discord"]);
+        assert!(!combined.wait_for_change(Duration::ZERO));
+    }
+
+    #[test]
+    fn either_skips_failing_detector() {
+        let mut combined = Combined::new(vec![Box::new(Failing), Box::new(ScriptedDetector::new([true]))], false);
+        assert_eq!(combined.active_apps().unwrap(), ["scripted"]);
+        // The failing detector is tried again, the other one isn't.
+        assert_eq!(next(&mut combined).unwrap(), ["scripted"]);
+    }
+
+    #[test]
+    fn fails_if_every_detector_fails() {
+        let mut combined = Combined::new(vec![Box::new(Failing), Box::new(Failing)], false);
+        assert!(combined.active_apps().is_err());
+        assert!(next(&mut combined).is_err());
+    }
+
+    #[test]
+    fn both_fails_with_any_detector() {
+        let mut combined = Combined::new(vec![Box::new(ScriptedDetector::new([true])), Box::new(Failing)], true);
+        assert!(combined.active_apps().is_err());
     }
-    Ok(false)
 }
@@ -0,0 +1,203 @@
+use super::ActivityDetector;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+/// Where Windows' CapabilityAccessManager records device use, below
+/// `HKEY_CURRENT_USER`.
+pub const CONSENT_STORE_KEY: &str = r"SOFTWARE\Microsoft\Windows\CurrentVersion\CapabilityAccessManager\ConsentStore";
+
+/// Subkey holding desktop applications; every other subkey of a capability
+/// is a packaged (Store/UWP) app.
+const NON_PACKAGED: &str = "NonPackaged";
+
+/// Read-only access to the registry keys the consent store detector needs.
+/// Missing keys and values read as empty, like a device that was never used.
+pub trait RegistryView {
+    /// Names of the direct subkeys of `path`.
+    fn subkeys(&self, path: &str) -> Vec<String>;
+    /// The `REG_QWORD` value `name` of the key at `path`.
+    fn u64_value(&self, path: &str, name: &str) -> Option<u64>;
+
+    /// Blocks until something below `path` changed or `timeout` passed, see
+    /// [`ActivityDetector::wait_for_change`]. Polls by default.
+    fn wait_for_change(&mut self, _path: &str, timeout: Duration) -> bool {
+        thread::sleep(timeout);
+        true
+    }
+}
+
+/// Detects device use from the consent store, where Windows records when
+/// each application started and stopped using a capability such as the
+/// camera (`webcam`) or the `microphone`. An application is using the device
+/// while its `LastUsedTimeStop` is 0.
+///
+/// The layout is
+/// ```text
+/// ConsentStore\webcam\MSTeams_8wekyb3d8bbwe                    packaged app
+/// ConsentStore\webcam\NonPackaged\C:#Program Files#Zoom#bin#Zoom.exe
+/// ```
+pub struct ConsentStoreDetector<R> {
+    registry: R,
+    key: String,
+}
+
+impl<R: RegistryView> ConsentStoreDetector<R> {
+    pub fn new(registry: R, capability: &str) -> Self {
+        Self {
+            registry,
+            key: format!(r"{}\{}", CONSENT_STORE_KEY, capability),
+        }
+    }
+
+    fn in_use(&self, path: &str) -> bool {
+        self.registry.u64_value(path, "LastUsedTimeStop") == Some(0)
+    }
+}
+
+impl<R: RegistryView> ActivityDetector for ConsentStoreDetector<R> {
+    fn active_apps(&mut self) -> io::Result<Vec<String>> {
+        let mut apps = Vec::new();
+        for subkey_name in self.registry.subkeys(&self.key) {
+            let sub_path = format!(r"{}\{}", self.key, subkey_name);
+            if subkey_name == NON_PACKAGED {
+                for exe in self.registry.subkeys(&sub_path) {
+                    if self.in_use(&format!(r"{}\{}", sub_path, exe)) {
+                        apps.push(non_packaged_name(&exe));
+                    }
+                }
+            } else if self.in_use(&sub_path) {
+                apps.push(packaged_name(&subkey_name));
+            }
+        }
+        Ok(apps)
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) -> bool {
+        self.registry.wait_for_change(&self.key, timeout)
+    }
+}
+
+/// Turns a `NonPackaged` subkey name back into the executable's file name.
+/// The subkeys are named after the executable's path with `\` replaced by
+/// `#`, e.g. `C:#Program Files#Zoom#bin#Zoom.exe`.
+pub fn non_packaged_name(subkey_name: &str) -> String {
+    subkey_name
+        .rsplit('#')
+        .next()
+        .unwrap_or(subkey_name)
+        .to_owned()
+}
+
+/// Strips the publisher hash from a package family name, e.g.
+/// `Microsoft.WindowsCamera_8wekyb3d8bbwe` becomes `Microsoft.WindowsCamera`.
+pub fn packaged_name(family_name: &str) -> String {
+    family_name
+        .rsplit_once('_')
+        .map_or(family_name, |(name, _)| name)
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// In-memory registry for running [`ConsentStoreDetector`] without Windows.
+    /// Keys are full paths; parent keys exist implicitly.
+    #[derive(Debug, Default, Clone)]
+    struct MockRegistry {
+        values: HashMap<String, HashMap<String, u64>>,
+    }
+
+    impl MockRegistry {
+        /// Sets the `REG_QWORD` value `name` of the key at `path`, creating the key.
+        fn set(&mut self, path: &str, name: &str, value: u64) -> &mut Self {
+            self.values
+                .entry(path.to_owned())
+                .or_default()
+                .insert(name.to_owned(), value);
+            self
+        }
+
+        /// Records that the app under `path` (relative to the consent store) is
+        /// using the device, or stopped using it if `active` is `false`.
+        fn set_in_use(&mut self, path: &str, active: bool) -> &mut Self {
+            let path = format!(r"{}\{}", CONSENT_STORE_KEY, path);
+            self.set(&path, "LastUsedTimeStart", 1);
+            self.set(&path, "LastUsedTimeStop", if active { 0 } else { 2 })
+        }
+    }
+
+    impl RegistryView for MockRegistry {
+        fn subkeys(&self, path: &str) -> Vec<String> {
+            let prefix = format!(r"{}\", path);
+            let mut names: Vec<String> = self
+                .values
+                .keys()
+                .filter_map(|key| key.strip_prefix(&prefix))
+                .map(|rest| rest.split('\\').next().unwrap_or(rest).to_owned())
+                .collect();
+            names.sort();
+            names.dedup();
+            names
+        }
+
+        fn u64_value(&self, path: &str, name: &str) -> Option<u64> {
+            self.values.get(path)?.get(name).copied()
+        }
+    }
+
+    fn detector(registry: MockRegistry) -> ConsentStoreDetector<MockRegistry> {
+        ConsentStoreDetector::new(registry, "webcam")
+    }
+
+    #[test]
+    fn reports_desktop_app_in_use() {
+        let mut registry = MockRegistry::default();
+        registry.set_in_use(r"webcam\NonPackaged\C:#Program Files#Zoom#bin#Zoom.exe", true);
+        assert_eq!(detector(registry).active_apps().unwrap(), ["Zoom.exe"]);
+    }
+
+    #[test]
+    fn reports_packaged_app_in_use() {
+        let mut registry = MockRegistry::default();
+        registry.set_in_use(r"webcam\Microsoft.WindowsCamera_8wekyb3d8bbwe", true);
+        assert_eq!(detector(registry).active_apps().unwrap(), ["Microsoft.WindowsCamera"]);
+    }
+
+    #[test]
+    fn ignores_apps_that_stopped() {
+        let mut registry = MockRegistry::default();
+        registry
+            .set_in_use(r"webcam\NonPackaged\C:#Windows#System32#svchost.exe", false)
+            .set_in_use(r"webcam\MSTeams_8wekyb3d8bbwe", false)
+            .set_in_use(r"webcam\NonPackaged\C:#Apps#obs64.exe", true);
+        assert_eq!(detector(registry).active_apps().unwrap(), ["obs64.exe"]);
+    }
+
+    #[test]
+    fn only_reads_the_requested_capability() {
+        let mut registry = MockRegistry::default();
+        registry.set_in_use(r"microphone\NonPackaged\C:#Apps#Discord.exe", true);
+        assert!(detector(registry).active_apps().unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_capability_key_is_idle() {
+        assert!(detector(MockRegistry::default()).active_apps().unwrap().is_empty());
+    }
+
+    #[test]
+    fn non_packaged_name_keeps_file_name() {
+        assert_eq!(non_packaged_name("C:#Program Files#Zoom#bin#Zoom.exe"), "Zoom.exe");
+        assert_eq!(non_packaged_name("Zoom.exe"), "Zoom.exe");
+    }
+
+    #[test]
+    fn packaged_name_strips_publisher_hash() {
+        assert_eq!(packaged_name("Microsoft.WindowsCamera_8wekyb3d8bbwe"), "Microsoft.WindowsCamera");
+        assert_eq!(packaged_name("Some_App_1234abcd"), "Some_App");
+        assert_eq!(packaged_name("NoPublisher"), "NoPublisher");
+    }
+}
@@ -0,0 +1,277 @@
+use super::ActivityDetector;
+use inotify::{Inotify, WatchMask};
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// When `/dev` can't be watched, unchanged scans stretch the polling interval
+/// up to this many times the caller's timeout.
+const MAX_BACKOFF: u32 = 8;
+
+/// Detects camera use by looking for processes holding a `/dev/video*`
+/// device open, as listed under `/proc/<pid>/fd`. Applications are named
+/// after the process's `comm`.
+///
+/// Only processes the current user may inspect are seen, which covers the
+/// user's own video call apps.
+///
+/// Between scans, inotify reports opens and closes of `/dev/video*`, so the
+/// process list is only walked when a camera was touched.
+pub struct ProcfsDetector {
+    root: PathBuf,
+    /// Watches `/dev` and its video devices; `None` until the first wait, or
+    /// when inotify isn't available and the detector polls instead.
+    watch: Option<Inotify>,
+    /// Fallback polling state: the last scan result, how many scans in a row
+    /// returned it, and how many waits passed since the last scan.
+    last_apps: Vec<String>,
+    unchanged_scans: u32,
+    skipped_waits: u32,
+}
+
+impl ProcfsDetector {
+    /// Creates a detector that scans `root` instead of `/proc`, e.g. a fake
+    /// procfs tree whose `fd` entries are symlinks to `/dev/video0`.
+    pub fn with_root(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            watch: None,
+            last_apps: Vec::new(),
+            unchanged_scans: 0,
+            skipped_waits: 0,
+        }
+    }
+
+    /// Sleeps `timeout` and reports a change every few waits, backing off
+    /// while the scans keep returning the same apps.
+    fn poll(&mut self, timeout: Duration) -> bool {
+        thread::sleep(timeout);
+        self.skipped_waits += 1;
+        if self.skipped_waits <= self.unchanged_scans.min(MAX_BACKOFF - 1) {
+            return false;
+        }
+        self.skipped_waits = 0;
+        true
+    }
+}
+
+impl Default for ProcfsDetector {
+    fn default() -> Self {
+        Self::with_root("/proc")
+    }
+}
+
+impl ActivityDetector for ProcfsDetector {
+    fn active_apps(&mut self) -> io::Result<Vec<String>> {
+        let mut apps = Vec::new();
+        for entry in fs::read_dir(&self.root)?.flatten() {
+            let is_pid = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()));
+            if is_pid && has_video_fd(&entry.path()) {
+                let app = process_name(&entry.path());
+                if !apps.contains(&app) {
+                    apps.push(app);
+                }
+            }
+        }
+        if apps == self.last_apps {
+            self.unchanged_scans = self.unchanged_scans.saturating_add(1);
+        } else {
+            self.unchanged_scans = 0;
+            self.last_apps = apps.clone();
+        }
+        Ok(apps)
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) -> bool {
+        let Some(inotify) = &mut self.watch else {
+            // Report a change once the watch is set up, so the caller scans
+            // no later than the first event.
+            match watch_video_devices() {
+                Ok(inotify) => {
+                    self.watch = Some(inotify);
+                    return true;
+                }
+                Err(_) => return self.poll(timeout),
+            }
+        };
+        match wait_readable(inotify.as_raw_fd(), timeout) {
+            Ok(false) => false,
+            Ok(true) => {
+                let mut buffer = [0; 1024];
+                let mut changed = false;
+                let mut devices_changed = false;
+                while let Ok(events) = inotify.read_events(&mut buffer) {
+                    for event in events {
+                        match event.name.and_then(|name| name.to_str()) {
+                            // A camera was plugged in or removed, watch the new set.
+                            Some(name) if name.starts_with("video") => devices_changed = true,
+                            // Some other device node in `/dev`.
+                            Some(_) => continue,
+                            None => {}
+                        }
+                        changed = true;
+                    }
+                }
+                if devices_changed {
+                    self.watch = None;
+                }
+                changed
+            }
+            Err(_) => {
+                self.watch = None;
+                self.poll(timeout)
+            }
+        }
+    }
+}
+
+/// Watches `/dev` for video devices coming and going, and every
+/// `/dev/video*` for being opened or closed.
+fn watch_video_devices() -> io::Result<Inotify> {
+    let inotify = Inotify::init()?;
+    let mut watches = inotify.watches();
+    watches.add("/dev", WatchMask::CREATE | WatchMask::DELETE)?;
+    for entry in fs::read_dir("/dev")?.flatten() {
+        let path = entry.path();
+        if is_video_device(&path) {
+            watches.add(&path, WatchMask::OPEN | WatchMask::CLOSE)?;
+        }
+    }
+    Ok(inotify)
+}
+
+/// Returns `true` if `fd` became readable within `timeout`.
+fn wait_readable(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+    // SAFETY: `pollfd` is a single valid entry that outlives the call.
+    match unsafe { libc::poll(&mut pollfd, 1, millis) } {
+        -1 => match io::Error::last_os_error() {
+            e if e.kind() == io::ErrorKind::Interrupted => Ok(false),
+            e => Err(e),
+        },
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Processes may exit or be off-limits while being scanned,
+/// so unreadable entries are skipped.
+fn has_video_fd(process: &Path) -> bool {
+    let Ok(fds) = fs::read_dir(process.join("fd")) else {
+        return false;
+    };
+    fds.flatten().any(|fd| {
+        fs::read_link(fd.path()).is_ok_and(|target| is_video_device(&target))
+    })
+}
+
+/// Falls back to the pid if the process exited in the meantime.
+fn process_name(process: &Path) -> String {
+    fs::read_to_string(process.join("comm"))
+        .map(|comm| comm.trim_end().to_owned())
+        .unwrap_or_else(|_| process.file_name().unwrap_or_default().to_string_lossy().into_owned())
+}
+
+fn is_video_device(path: &Path) -> bool {
+    path.parent() == Some(Path::new("/dev"))
+        && path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with("video"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    /// Adds a process to a fake procfs tree, with `fd` entries linking to
+    /// `targets`.
+    fn add_process(root: &Path, pid: &str, comm: &str, targets: &[&str]) {
+        let fd = root.join(pid).join("fd");
+        fs::create_dir_all(&fd).unwrap();
+        fs::write(root.join(pid).join("comm"), format!("{}\n", comm)).unwrap();
+        for (n, target) in targets.iter().enumerate() {
+            symlink(target, fd.join(n.to_string())).unwrap();
+        }
+    }
+
+    #[test]
+    fn reports_processes_with_video_fds() {
+        let root = TempDir::new().unwrap();
+        add_process(root.path(), "100", "zoom", &["/dev/null", "/dev/video0"]);
+        add_process(root.path(), "200", "bash", &["/dev/pts/0", "/dev/null"]);
+        add_process(root.path(), "300", "obs", &["/dev/video2"]);
+
+        let mut apps = ProcfsDetector::with_root(root.path()).active_apps().unwrap();
+        apps.sort();
+        assert_eq!(apps, ["obs", "zoom"]);
+    }
+
+    #[test]
+    fn ignores_non_video_devices_outside_dev() {
+        let root = TempDir::new().unwrap();
+        add_process(root.path(), "100", "cat", &["/tmp/dev/video0", "/dev/v4l/video0"]);
+
+        assert!(ProcfsDetector::with_root(root.path()).active_apps().unwrap().is_empty());
+    }
+
+    #[test]
+    fn skips_non_numeric_directories() {
+        let root = TempDir::new().unwrap();
+        add_process(root.path(), "self", "zoom", &["/dev/video0"]);
+        add_process(root.path(), "12ab", "zoom", &["/dev/video0"]);
+
+        assert!(ProcfsDetector::with_root(root.path()).active_apps().unwrap().is_empty());
+    }
+
+    #[test]
+    fn skips_unreadable_fd_directory() {
+        let root = TempDir::new().unwrap();
+        add_process(root.path(), "100", "zoom", &["/dev/video0"]);
+        // A file in place of the directory can't be listed, even by root,
+        // like the `fd` directory of another user's process.
+        fs::create_dir(root.path().join("200")).unwrap();
+        fs::write(root.path().join("200").join("fd"), "").unwrap();
+        fs::write(root.path().join("200").join("comm"), "other\n").unwrap();
+
+        assert_eq!(ProcfsDetector::with_root(root.path()).active_apps().unwrap(), ["zoom"]);
+    }
+
+    #[test]
+    fn reports_each_process_name_once() {
+        let root = TempDir::new().unwrap();
+        add_process(root.path(), "100", "chrome", &["/dev/video0"]);
+        add_process(root.path(), "101", "chrome", &["/dev/video0", "/dev/video1"]);
+
+        assert_eq!(ProcfsDetector::with_root(root.path()).active_apps().unwrap(), ["chrome"]);
+    }
+
+    #[test]
+    fn falls_back_to_pid_without_comm() {
+        let root = TempDir::new().unwrap();
+        add_process(root.path(), "100", "zoom", &["/dev/video0"]);
+        fs::remove_file(root.path().join("100").join("comm")).unwrap();
+
+        assert_eq!(ProcfsDetector::with_root(root.path()).active_apps().unwrap(), ["100"]);
+    }
+
+    #[test]
+    fn missing_root_is_an_error() {
+        let root = TempDir::new().unwrap();
+        let mut detector = ProcfsDetector::with_root(root.path().join("missing"));
+        assert!(detector.active_apps().is_err());
+    }
+}
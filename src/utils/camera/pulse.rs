@@ -0,0 +1,84 @@
+use super::ActivityDetector;
+use std::io;
+use std::process::Command;
+
+/// Detects microphone use through the PulseAudio API, which PipeWire also
+/// serves via `pipewire-pulse`. Every recording stream shows up as a source
+/// output.
+pub struct PulseDetector;
+
+impl ActivityDetector for PulseDetector {
+    fn active_apps(&mut self) -> io::Result<Vec<String>> {
+        // The listing is parsed by its English headers, which pactl translates.
+        let output = Command::new("pactl")
+            .args(["list", "source-outputs"])
+            .env("LC_ALL", "C")
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("pactl exited with {}", output.status)));
+        }
+        Ok(source_output_apps(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+/// Reads the `application.name` of every stream in the output of
+/// `pactl list source-outputs`. Streams without a name are reported as
+/// `unknown`.
+pub fn source_output_apps(listing: &str) -> Vec<String> {
+    let mut apps: Vec<String> = Vec::new();
+    for stream in listing.split("Source Output #").skip(1) {
+        let name = stream
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("application.name = "))
+            .map(|value| value.trim_matches('"').to_owned())
+            .next()
+            .unwrap_or_else(|| "unknown".to_owned());
+        if !apps.contains(&name) {
+            apps.push(name);
+        }
+    }
+    apps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTING: &str = r#"Source Output #41
+	Driver: protocol-native.c
+	Owner Module: 10
+	Client: 57
+	Source: 2
+	Properties:
+		media.name = "RecordStream"
+		application.name = "Firefox"
+		application.process.binary = "firefox"
+
+Source Output #42
+	Driver: protocol-native.c
+	Properties:
+		media.name = "audio capture"
+		application.name = "ZOOM VoiceEngine"
+
+Source Output #43
+	Driver: PipeWire
+	Properties:
+		media.name = "Firefox"
+		application.name = "Firefox"
+
+Source Output #44
+	Driver: PipeWire
+	Properties:
+		media.name = "capture"
+"#;
+
+    #[test]
+    fn reads_application_names() {
+        assert_eq!(source_output_apps(LISTING), ["Firefox", "ZOOM VoiceEngine", "unknown"]);
+    }
+
+    #[test]
+    fn empty_listing_has_no_apps() {
+        assert!(source_output_apps("").is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+use super::consent::{ConsentStoreDetector, RegistryView};
+use std::ffi::c_void;
+use std::io;
+use std::thread;
+use std::time::Duration;
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0};
+use windows::Win32::System::Registry::{
+    RegNotifyChangeKeyValue, HKEY, REG_NOTIFY_CHANGE_LAST_SET, REG_NOTIFY_CHANGE_NAME,
+};
+use windows::Win32::System::Threading::{CreateEventW, WaitForSingleObject};
+use winreg::enums::{HKEY_CURRENT_USER, KEY_NOTIFY, KEY_READ};
+use winreg::RegKey;
+
+/// The current user's registry hive.
+#[derive(Default)]
+pub struct WinRegistry {
+    /// Change notification for the watched key, set up on the first wait.
+    watch: Option<KeyWatch>,
+}
+
+impl RegistryView for WinRegistry {
+    fn subkeys(&self, path: &str) -> Vec<String> {
+        // Try to open the key; if it doesn't exist, the device was never used
+        match RegKey::predef(HKEY_CURRENT_USER).open_subkey(path) {
+            Ok(key) => key.enum_keys().flatten().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn u64_value(&self, path: &str, name: &str) -> Option<u64> {
+        RegKey::predef(HKEY_CURRENT_USER)
+            .open_subkey(path)
+            .and_then(|key| key.get_value::<u64, _>(name))
+            .ok()
+    }
+
+    fn wait_for_change(&mut self, path: &str, timeout: Duration) -> bool {
+        let Some(watch) = &self.watch else {
+            // Report a change once the watch is armed, so the caller reads a
+            // state no later than the first notification. Keys that can't be
+            // watched (e.g. the device was never used) are polled.
+            match KeyWatch::new(path) {
+                Ok(watch) => self.watch = Some(watch),
+                Err(_) => thread::sleep(timeout),
+            }
+            return true;
+        };
+        if !watch.wait(timeout) {
+            return false;
+        }
+        // Notifications fire once; re-arm before the caller reads the key.
+        if watch.arm().is_err() {
+            self.watch = None;
+        }
+        true
+    }
+}
+
+/// Asynchronous `RegNotifyChangeKeyValue` registration on a key and its
+/// subkeys, signalling `event` when a subkey or value changes.
+struct KeyWatch {
+    key: RegKey,
+    event: HANDLE,
+}
+
+// SAFETY: the event handle is owned by the watch and may be waited on and
+// closed from any thread.
+unsafe impl Send for KeyWatch {}
+
+impl KeyWatch {
+    fn new(path: &str) -> io::Result<Self> {
+        let key = RegKey::predef(HKEY_CURRENT_USER).open_subkey_with_flags(path, KEY_READ | KEY_NOTIFY)?;
+        // SAFETY: creates an unnamed auto-reset event without security attributes.
+        let event = unsafe { CreateEventW(None, false, false, PCWSTR::null()) }?;
+        let watch = Self { key, event };
+        watch.arm()?;
+        Ok(watch)
+    }
+
+    fn arm(&self) -> io::Result<()> {
+        let key = HKEY(self.key.raw_handle() as *mut c_void);
+        // SAFETY: the key and event stay open as long as the watch exists.
+        let status = unsafe {
+            RegNotifyChangeKeyValue(
+                key,
+                true,
+                REG_NOTIFY_CHANGE_NAME | REG_NOTIFY_CHANGE_LAST_SET,
+                Some(self.event),
+                true,
+            )
+        };
+        if status.is_err() {
+            return Err(io::Error::from_raw_os_error(status.0 as i32));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the key changed within `timeout`.
+    fn wait(&self, timeout: Duration) -> bool {
+        let millis = timeout.as_millis().min(u32::MAX as u128) as u32;
+        // SAFETY: the event handle is valid until the watch is dropped.
+        unsafe { WaitForSingleObject(self.event, millis) == WAIT_OBJECT_0 }
+    }
+}
+
+impl Drop for KeyWatch {
+    fn drop(&mut self) {
+        // SAFETY: the event was created by `new` and is closed only here.
+        let _ = unsafe { CloseHandle(self.event) };
+    }
+}
+
+/// Consent store detector reading the real registry.
+pub type RegistryDetector = ConsentStoreDetector<WinRegistry>;
+
+impl RegistryDetector {
+    pub fn webcam() -> Self {
+        Self::new(WinRegistry::default(), "webcam")
+    }
+
+    pub fn microphone() -> Self {
+        Self::new(WinRegistry::default(), "microphone")
+    }
+}
@@ -0,0 +1,265 @@
+use crate::utils::light::KeyLightClient;
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use reqwest::Client;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// mDNS service type advertised by Elgato lights.
+pub const ELGATO_SERVICE: &str = "_elg._tcp.local.";
+/// Port of the Elgato HTTP API.
+pub const ELGATO_PORT: u16 = 9123;
+/// How long [`locate`] listens for mDNS answers.
+const LOCATE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Shortest prefix accepted by [`scan_subnet`], larger ranges take too long to probe.
+const MIN_SCAN_PREFIX: u8 = 16;
+
+/// Errors returned by light discovery.
+#[derive(Debug)]
+pub enum DiscoveryError {
+    Mdns(mdns_sd::Error),
+    Http(reqwest::Error),
+    /// The scan range is not an IPv4 CIDR like `192.168.1.0/24`.
+    InvalidCidr(String),
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiscoveryError::Mdns(e) => write!(f, "mDNS error: {}", e),
+            DiscoveryError::Http(e) => write!(f, "HTTP client error: {}", e),
+            DiscoveryError::InvalidCidr(range) => write!(f, "invalid scan range: {}", range),
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+impl From<mdns_sd::Error> for DiscoveryError {
+    fn from(e: mdns_sd::Error) -> Self {
+        DiscoveryError::Mdns(e)
+    }
+}
+
+impl From<reqwest::Error> for DiscoveryError {
+    fn from(e: reqwest::Error) -> Self {
+        DiscoveryError::Http(e)
+    }
+}
+
+/// A light found on the local network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredLight {
+    /// Instance name advertised by the device, e.g. `Elgato Key Light 1A2B`.
+    pub name: String,
+    pub hostname: String,
+    pub ip_address: String,
+    pub port: u16,
+    /// Serial number, if the discovery method reads `/elgato/accessory-info`.
+    pub serial_number: String,
+}
+
+/// Browses for `service_type` via mDNS and returns every instance resolved
+/// within `timeout`.
+///
+/// # Example
+/// ```
+/// let lights = browse_mdns(ELGATO_SERVICE, Duration::from_secs(3))?;
+/// ```
+pub fn browse_mdns(service_type: &str, timeout: Duration) -> Result<Vec<DiscoveredLight>, DiscoveryError> {
+    let mdns = ServiceDaemon::new()?;
+    let receiver = mdns.browse(service_type)?;
+    let deadline = Instant::now() + timeout;
+    let mut found: Vec<DiscoveredLight> = Vec::new();
+
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        let Ok(event) = receiver.recv_timeout(remaining) else {
+            break;
+        };
+        if let ServiceEvent::ServiceResolved(info) = event {
+            // Prefer IPv4, the lights don't reliably answer on IPv6.
+            let Some(ip) = info
+                .get_addresses()
+                .iter()
+                .min_by_key(|ip| matches!(ip, IpAddr::V6(_)))
+            else {
+                continue;
+            };
+            let name = info
+                .get_fullname()
+                .strip_suffix(service_type)
+                .unwrap_or(info.get_fullname())
+                .trim_end_matches('.')
+                .to_string();
+            let light = DiscoveredLight {
+                name,
+                hostname: info.get_hostname().trim_end_matches('.').to_string(),
+                ip_address: ip.to_string(),
+                port: info.get_port(),
+                serial_number: String::new(),
+            };
+            if !found.contains(&light) {
+                found.push(light);
+            }
+        }
+    }
+
+    let _ = mdns.stop_browse(service_type);
+    let _ = mdns.shutdown();
+    Ok(found)
+}
+
+/// Finds the current address of the light with `serial_number`, first by
+/// resolving its mDNS `hostname` (if known), then by browsing for Elgato
+/// services. Candidates are confirmed via `/elgato/accessory-info`.
+pub async fn locate(http: &Client, serial_number: &str, hostname: &str, port: u16) -> Option<String> {
+    let mut candidates: Vec<String> = Vec::new();
+    if !hostname.is_empty() {
+        if let Ok(addrs) = tokio::net::lookup_host((hostname, port)).await {
+            candidates.extend(addrs.filter(|a| a.is_ipv4()).map(|a| a.ip().to_string()));
+        }
+    }
+    // The mDNS daemon blocks while it listens for answers.
+    if let Ok(Ok(found)) = tokio::task::spawn_blocking(|| browse_mdns(ELGATO_SERVICE, LOCATE_TIMEOUT)).await {
+        candidates.extend(found.into_iter().map(|l| l.ip_address));
+    }
+    for ip in candidates {
+        let client = KeyLightClient::with_client(http.clone(), &ip, port);
+        if client
+            .accessory_info()
+            .await
+            .is_ok_and(|info| info.serial_number == serial_number)
+        {
+            return Some(ip);
+        }
+    }
+    None
+}
+
+/// Probes every host of an IPv4 CIDR range for `/elgato/accessory-info` on
+/// `port`, with at most `concurrency` requests in flight. Meant for networks
+/// where multicast is blocked.
+pub async fn scan_subnet(
+    cidr: &str,
+    port: u16,
+    concurrency: usize,
+    timeout: Duration,
+) -> Result<Vec<DiscoveredLight>, DiscoveryError> {
+    let hosts = cidr_hosts(cidr)?;
+    let http = Client::builder().timeout(timeout).build()?;
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut probes = JoinSet::new();
+
+    for host in hosts {
+        let client = KeyLightClient::with_client(http.clone(), &host.to_string(), port);
+        let permits = permits.clone();
+        probes.spawn(async move {
+            let _permit = permits.acquire_owned().await.ok()?;
+            let info = client.accessory_info().await.ok()?;
+            let name = if info.display_name.is_empty() {
+                info.product_name
+            } else {
+                info.display_name
+            };
+            Some(DiscoveredLight {
+                name,
+                hostname: String::new(),
+                ip_address: host.to_string(),
+                port,
+                serial_number: info.serial_number,
+            })
+        });
+    }
+
+    let mut found = Vec::new();
+    while let Some(res) = probes.join_next().await {
+        if let Ok(Some(light)) = res {
+            found.push(light);
+        }
+    }
+    found.sort_by_key(|l| l.ip_address.parse::<Ipv4Addr>().ok());
+    Ok(found)
+}
+
+/// Expands an IPv4 CIDR like `192.168.1.0/24` into its host addresses,
+/// leaving out the network and broadcast address where there are any.
+fn cidr_hosts(cidr: &str) -> Result<Vec<Ipv4Addr>, DiscoveryError> {
+    let invalid = || DiscoveryError::InvalidCidr(cidr.to_string());
+    let (addr, prefix) = cidr.trim().split_once('/').ok_or_else(invalid)?;
+    let addr: Ipv4Addr = addr.parse().map_err(|_| invalid())?;
+    let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+    if !(MIN_SCAN_PREFIX..=32).contains(&prefix) {
+        return Err(invalid());
+    }
+
+    let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+    let network = u32::from(addr) & mask;
+    let broadcast = network | !mask;
+    let range = if prefix >= 31 {
+        network..=broadcast
+    } else {
+        network + 1..=broadcast - 1
+    };
+    Ok(range.map(Ipv4Addr::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdns_sd::ServiceInfo;
+
+    #[test]
+    fn browse_finds_local_responder() {
+        let responder = ServiceDaemon::new().unwrap();
+        let name = format!("Elgato Key Light Test {}", std::process::id());
+        let info = ServiceInfo::new(ELGATO_SERVICE, &name, "keylight-test.local.", "", 9124, None)
+            .unwrap()
+            .enable_addr_auto();
+        responder.register(info).unwrap();
+
+        let found = browse_mdns(ELGATO_SERVICE, Duration::from_secs(3)).unwrap();
+        let _ = responder.shutdown();
+
+        let light = found.iter().find(|l| l.name == name).expect("responder not found");
+        assert_eq!(light.hostname, "keylight-test.local");
+        assert_eq!(light.port, 9124);
+        assert!(light.serial_number.is_empty());
+    }
+
+    #[test]
+    fn cidr_hosts_skips_network_and_broadcast() {
+        let hosts = cidr_hosts("192.168.1.77/24").unwrap();
+        assert_eq!(hosts.len(), 254);
+        assert_eq!(hosts[0], Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(hosts[253], Ipv4Addr::new(192, 168, 1, 254));
+    }
+
+    #[test]
+    fn cidr_hosts_point_to_point_and_single_host() {
+        assert_eq!(
+            cidr_hosts("10.0.0.1/31").unwrap(),
+            [Ipv4Addr::new(10, 0, 0, 0), Ipv4Addr::new(10, 0, 0, 1)]
+        );
+        assert_eq!(cidr_hosts(" 10.0.0.5/32 ").unwrap(), [Ipv4Addr::new(10, 0, 0, 5)]);
+    }
+
+    #[test]
+    fn cidr_hosts_rejects_large_ranges() {
+        assert!(cidr_hosts("10.0.0.0/16").is_ok());
+        assert!(matches!(cidr_hosts("10.0.0.0/15"), Err(DiscoveryError::InvalidCidr(_))));
+        assert!(matches!(cidr_hosts("0.0.0.0/0"), Err(DiscoveryError::InvalidCidr(_))));
+    }
+
+    #[test]
+    fn cidr_hosts_rejects_bad_input() {
+        for range in ["", "192.168.1.0", "192.168.1.0/", "192.168.1/24", "192.168.1.0/33", "host.local/24", "::1/128"] {
+            assert!(
+                matches!(cidr_hosts(range), Err(DiscoveryError::InvalidCidr(_))),
+                "{range:?} was accepted"
+            );
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use eframe::egui::IconData;
+#[cfg(windows)]
 use tray_icon::Icon;
 
 /// Represents an image used for tray and egui icons.
@@ -10,6 +11,7 @@ pub struct IconImage {
 
 impl IconImage {
     /// Converts the icon image into a tray icon.
+    #[cfg(windows)]
     pub fn to_tray_icon(&self) -> Icon {
         // Create tray icon; cloning rgba is necessary as Icon consumes it.
         Icon::from_rgba(self.rgba.clone(), self.width, self.height)
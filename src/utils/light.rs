@@ -1,80 +1,300 @@
-use reqwest::{self, Client};
-use serde_json::json;
+use reqwest::{self, Client, Method, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
 
-#[tokio::main]
-pub async fn set_state(
-    turn_on: bool,
-    ip: &str,
-    port: u16,
-    brightness: u8,
-    temperature: u16,
-) -> Result<(), reqwest::Error> {
-    let url = format!("http://{}:{}/elgato/lights", ip, port);
-    let body = json!({
-        "numberOfLights": 1,
-        "lights": [
-            {
-                "on": turn_on as u8,
-                "brightness": brightness,
-                "temperature": kelvin_to_api_temp(temperature),
-            }
-        ]
-    });
-
-    let req = Client::new()
-        .put(&url)
-        .header("Content-Type", "application/json")
-        .json(&body)
-        .send()
-        .await?;
-    if req.status().is_success() {
-        println!(
-            "Light is turned {}, brightness: {}, temperature: {}",
-            if turn_on { "on" } else { "off" },
+/// Addresses of hostnames resolved by any [`KeyLightClient`].
+static RESOLVED: LazyLock<Mutex<HashMap<String, IpAddr>>> = LazyLock::new(Default::default);
+
+/// Errors returned by [`KeyLightClient`].
+#[derive(Debug)]
+pub enum LightError {
+    /// The hostname of the device could not be resolved.
+    Resolve(String, io::Error),
+    /// The request could not be sent or the response body could not be read.
+    Http(reqwest::Error),
+    /// The device answered with a non-success status code.
+    Status(StatusCode),
+    /// The device has no light at the requested index.
+    NoSuchLight(usize),
+}
+
+impl fmt::Display for LightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LightError::Resolve(host, e) => write!(f, "could not resolve {}: {}", host, e),
+            LightError::Http(e) => write!(f, "request failed: {}", e),
+            LightError::Status(status) => write!(f, "device responded with {}", status),
+            LightError::NoSuchLight(index) => write!(f, "device has no light with index {}", index),
+        }
+    }
+}
+
+impl std::error::Error for LightError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LightError::Resolve(_, e) => Some(e),
+            LightError::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for LightError {
+    fn from(e: reqwest::Error) -> Self {
+        LightError::Http(e)
+    }
+}
+
+/// State of a single light as carried by the `/elgato/lights` endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LightState {
+    pub on: u8,
+    pub brightness: u8,
+    /// Temperature in API units, see [`kelvin_to_api_temp`].
+    pub temperature: u16,
+}
+
+impl LightState {
+    pub fn new(on: bool, brightness: u8, kelvin: u16) -> Self {
+        Self {
+            on: on as u8,
             brightness,
-            temperature
-        );
-    } else {
-        println!("Failed to set light state");
-        println!("Status: {}", req.status());
-        match req.json::<serde_json::Value>().await {
-            Ok(data) => println!("Data sent: {}", data),
-            Err(e) => println!("Failed to get response body: {}", e),
+            temperature: kelvin_to_api_temp(kelvin),
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on != 0
+    }
+
+    pub fn kelvin(&self) -> u16 {
+        api_temp_to_kelvin(self.temperature)
+    }
+}
+
+/// Request and response body of the `/elgato/lights` endpoint.
+/// A device may report several lights in one payload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lights {
+    pub number_of_lights: usize,
+    pub lights: Vec<LightState>,
+}
+
+impl From<Vec<LightState>> for Lights {
+    fn from(lights: Vec<LightState>) -> Self {
+        Self {
+            number_of_lights: lights.len(),
+            lights,
         }
-        println!("Data sent: {}", body);
     }
-    Ok(())
 }
 
-#[tokio::main]
-pub async fn get_state(ip: &str, port: u16) -> Result<(bool, u8, u16), Box<dyn std::error::Error>> {
-    let url = format!("http://{}:{}/elgato/lights", ip, port);
-    let resp = Client::new()
-        .get(&url)
-        .send()
-        .await?
-        .json::<serde_json::Value>()
-        .await?;
-
-    if let Some(lights) = resp
-        .get("lights")
-        .and_then(|l| l.as_array())
-        .and_then(|a| a.first())
-    {
-        let on = lights.get("on").and_then(|o| o.as_u64()).unwrap_or(0) != 0;
-        let brightness = lights
-            .get("brightness")
-            .and_then(|b| b.as_u64())
-            .unwrap_or(0) as u8;
-        let temperature = lights
-            .get("temperature")
-            .and_then(|t| t.as_u64())
-            .map(|v| api_temp_to_kelvin(v as u16))
-            .unwrap_or(0);
-        Ok((on, brightness, temperature))
-    } else {
-        // Return a custom error message
-        Err("Failed to parse light state".into())
+/// Response body of the `/elgato/accessory-info` endpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AccessoryInfo {
+    pub product_name: String,
+    pub hardware_board_type: u32,
+    pub firmware_build_number: u32,
+    pub firmware_version: String,
+    pub serial_number: String,
+    pub display_name: String,
+    pub features: Vec<String>,
+}
+
+/// Request body for renaming a device via `/elgato/accessory-info`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DisplayName<'a> {
+    display_name: &'a str,
+}
+
+/// Persistent device settings carried by the `/elgato/lights/settings` endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LightSettings {
+    /// `1` restores the last state on power-on, `2` uses the values below.
+    pub power_on_behavior: u8,
+    pub power_on_brightness: u8,
+    /// Temperature in API units, see [`kelvin_to_api_temp`].
+    pub power_on_temperature: u16,
+    pub switch_on_duration_ms: u32,
+    pub switch_off_duration_ms: u32,
+    pub color_change_duration_ms: u32,
+}
+
+/// Number of on/off cycles used when a device can't identify itself.
+const IDENTIFY_BLINKS: usize = 3;
+const IDENTIFY_BLINK_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Client for the local HTTP API of a single Elgato device.
+///
+/// The device may be given as an IP address or a hostname. Hostnames are
+/// resolved once and cached; when the cached address stops answering, it is
+/// resolved again and the request retried.
+///
+/// Cloning is cheap; clones share the underlying connection pool.
+#[derive(Debug, Clone)]
+pub struct KeyLightClient {
+    http: Client,
+    host: String,
+    port: u16,
+}
+
+impl KeyLightClient {
+    /// Creates a client that reuses an existing `reqwest::Client`, so that
+    /// requests to several devices share one connection pool.
+    pub fn with_client(http: Client, host: &str, port: u16) -> Self {
+        Self {
+            http,
+            host: host.to_owned(),
+            port,
+        }
+    }
+
+    async fn address(&self) -> Result<IpAddr, LightError> {
+        if let Ok(ip) = self.host.parse::<IpAddr>() {
+            return Ok(ip);
+        }
+        if let Some(ip) = RESOLVED.lock().unwrap().get(&self.host) {
+            return Ok(*ip);
+        }
+        let resolve_err = |e| LightError::Resolve(self.host.clone(), e);
+        // Prefer IPv4, the lights don't reliably answer on IPv6.
+        let ip = tokio::net::lookup_host((self.host.as_str(), self.port))
+            .await
+            .map_err(resolve_err)?
+            .map(|addr| addr.ip())
+            .min_by_key(|ip| ip.is_ipv6())
+            .ok_or_else(|| resolve_err(io::ErrorKind::NotFound.into()))?;
+        RESOLVED.lock().unwrap().insert(self.host.clone(), ip);
+        Ok(ip)
+    }
+
+    /// Drops the cached address of the hostname.
+    /// Returns `false` if there was nothing to re-resolve.
+    fn forget_address(&self) -> bool {
+        RESOLVED.lock().unwrap().remove(&self.host).is_some()
+    }
+
+    async fn try_send<T: Serialize>(&self, method: Method, path: &str, body: Option<&T>) -> Result<Response, LightError> {
+        let addr = SocketAddr::new(self.address().await?, self.port);
+        let mut req = self.http.request(method, format!("http://{}/elgato/{}", addr, path));
+        if let Some(body) = body {
+            req = req.json(body);
+        }
+        Ok(req.send().await?)
+    }
+
+    async fn send<T: Serialize>(&self, method: Method, path: &str, body: Option<&T>) -> Result<Response, LightError> {
+        let resp = match self.try_send(method.clone(), path, body).await {
+            // The cached address may be stale, resolve the hostname again.
+            Err(LightError::Http(e)) if e.is_connect() && self.forget_address() => {
+                self.try_send(method, path, body).await?
+            }
+            res => res?,
+        };
+        if !resp.status().is_success() {
+            return Err(LightError::Status(resp.status()));
+        }
+        Ok(resp)
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, LightError> {
+        let resp = self.send(Method::GET, path, None::<&()>).await?;
+        Ok(resp.json::<T>().await?)
+    }
+
+    async fn put<T: Serialize>(&self, path: &str, body: &T) -> Result<(), LightError> {
+        self.send(Method::PUT, path, Some(body)).await?;
+        Ok(())
+    }
+
+    async fn post(&self, path: &str) -> Result<(), LightError> {
+        self.send(Method::POST, path, None::<&()>).await?;
+        Ok(())
+    }
+
+    /// Returns the states of every light reported by the device.
+    pub async fn lights(&self) -> Result<Vec<LightState>, LightError> {
+        Ok(self.get::<Lights>("lights").await?.lights)
+    }
+
+    /// Sets the state of every light on the device at once.
+    pub async fn set_lights(&self, lights: Vec<LightState>) -> Result<(), LightError> {
+        self.put("lights", &Lights::from(lights)).await
+    }
+
+    /// Returns the state of the light at `index`.
+    pub async fn light(&self, index: usize) -> Result<LightState, LightError> {
+        self.lights()
+            .await?
+            .get(index)
+            .copied()
+            .ok_or(LightError::NoSuchLight(index))
+    }
+
+    /// Sets the state of the light at `index`.
+    ///
+    /// The device applies the `lights` array by position, so for any index but
+    /// the first the current states are fetched and sent back with one entry
+    /// replaced.
+    pub async fn set_light(&self, index: usize, state: LightState) -> Result<(), LightError> {
+        let lights = if index == 0 {
+            vec![state]
+        } else {
+            let mut lights = self.lights().await?;
+            *lights.get_mut(index).ok_or(LightError::NoSuchLight(index))? = state;
+            lights
+        };
+        self.set_lights(lights).await
+    }
+
+    /// Returns the product, firmware and naming details of the device.
+    pub async fn accessory_info(&self) -> Result<AccessoryInfo, LightError> {
+        self.get("accessory-info").await
+    }
+
+    /// Changes the name the device reports as `displayName`.
+    pub async fn set_display_name(&self, name: &str) -> Result<(), LightError> {
+        self.put("accessory-info", &DisplayName { display_name: name }).await
+    }
+
+    /// Makes the device flash so it can be told apart from others.
+    ///
+    /// Devices without the `/elgato/identify` endpoint get a short on/off
+    /// blink of the light at `index` instead, after which its state is restored.
+    pub async fn identify(&self, index: usize) -> Result<(), LightError> {
+        match self.post("identify").await {
+            Err(LightError::Status(_)) => self.blink(index).await,
+            res => res,
+        }
+    }
+
+    async fn blink(&self, index: usize) -> Result<(), LightError> {
+        let original = self.light(index).await?;
+        for i in 0..IDENTIFY_BLINKS * 2 {
+            let on = (i % 2 == 0) != original.is_on();
+            self.set_light(index, LightState { on: on as u8, ..original }).await?;
+            tokio::time::sleep(IDENTIFY_BLINK_INTERVAL).await;
+        }
+        self.set_light(index, original).await
+    }
+
+    /// Returns the persistent power-on and transition settings.
+    pub async fn settings(&self) -> Result<LightSettings, LightError> {
+        self.get("lights/settings").await
+    }
+
+    /// Replaces the persistent power-on and transition settings.
+    pub async fn set_settings(&self, settings: &LightSettings) -> Result<(), LightError> {
+        self.put("lights/settings", settings).await
     }
 }
 